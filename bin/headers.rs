@@ -0,0 +1,60 @@
+//! Header data tooling for offline proving.
+//!
+//! To export a flat file of raw 80-byte headers from RPC:
+//!
+//!     `cargo run --release --bin headers export <path> <start_height> <end_height>`
+//!
+//! The exported file can be used by the circuits by setting `HEADER_SOURCE=file`,
+//! `HEADERS_FILE=<path>` and `HEADERS_FILE_START_HEIGHT=<start_height>`.
+//!
+//! To pre-warm the on-disk header cache configured with `HEADERS_CACHE`:
+//!
+//!     `cargo run --release --bin headers warm <start_height> <end_height>`
+//!
+use btcx::input::error::{FetchError, FetchResult};
+use btcx::input::file::export_headers;
+use btcx::input::registry::{CacheConfig, HeaderSourceConfig};
+
+fn warm_cache(start_height: u64, end_height: u64) -> FetchResult<()> {
+    let cache =
+        CacheConfig::from_env()?.ok_or(FetchError::ConfigMissing("HEADERS_CACHE".to_string()))?;
+    let mut source = cache.wrap(HeaderSourceConfig::from_env()?.build()?)?;
+
+    for height in (start_height..=end_height).step_by(2016) {
+        let count = (end_height - height + 1).min(2016) as usize;
+        source.get_headers_by_height(height, count)?;
+        log::info!("cached headers up to {}", height + count as u64 - 1);
+    }
+
+    Ok(())
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+
+    match args.get(1).map(|command| command.as_str()) {
+        Some("export") if args.len() == 5 => {
+            let path = &args[2];
+            let start_height = args[3].parse().unwrap();
+            let end_height = args[4].parse().unwrap();
+
+            HeaderSourceConfig::rpc_from_env()
+                .and_then(|config| config.build())
+                .and_then(|mut source| {
+                    export_headers(source.as_mut(), path, start_height, end_height)
+                })
+                .unwrap_or_else(|e| panic!("failed to export headers: {}", e));
+        }
+        Some("warm") if args.len() == 4 => {
+            let start_height = args[2].parse().unwrap();
+            let end_height = args[3].parse().unwrap();
+
+            warm_cache(start_height, end_height)
+                .unwrap_or_else(|e| panic!("failed to warm headers cache: {}", e));
+        }
+        _ => panic!(
+            "Usage: headers export <path> <start_height> <end_height> | \
+             headers warm <start_height> <end_height>"
+        ),
+    }
+}
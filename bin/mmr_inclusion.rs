@@ -0,0 +1,16 @@
+//! To build the binary:
+//!
+//!     `cargo build --release --bin mmr_inclusion`
+//!
+//! To prove the circuit using evm io:
+//!
+//!    `./target/release/mmr_inclusion prove --input-json src/bin/circuit_function_evm_input.json`
+//!
+//! Note that this circuit will not work with field-based io.
+//!
+use btcx::mmr::MmrInclusionCircuit;
+use plonky2x::backend::function::Plonky2xFunction;
+
+fn main() {
+    MmrInclusionCircuit::entrypoint();
+}
@@ -0,0 +1,25 @@
+//! To build the binary:
+//!
+//!     `cargo build --release --bin op_return`
+//!
+//! To prove the circuit using evm io:
+//!
+//!    `./target/release/op_return prove --input-json src/bin/circuit_function_evm_input.json`
+//!
+//! Note that this circuit will not work with field-based io.
+//!
+use btcx::tx::OpReturnCircuit;
+use plonky2x::backend::function::Plonky2xFunction;
+
+fn main() {
+    let max_tx_bytes = std::env::var("MAX_TX_BYTES").unwrap().parse().unwrap();
+
+    match max_tx_bytes {
+        256 => OpReturnCircuit::<256>::entrypoint(),
+        512 => OpReturnCircuit::<512>::entrypoint(),
+        1024 => OpReturnCircuit::<1024>::entrypoint(),
+        2048 => OpReturnCircuit::<2048>::entrypoint(),
+        4096 => OpReturnCircuit::<4096>::entrypoint(),
+        _ => panic!("Unsupported max transaction size"),
+    }
+}
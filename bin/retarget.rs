@@ -12,27 +12,42 @@
 //!
 //! Note that this circuit will not work with field-based io.
 //!
+//! The network is selected with `NETWORK`: mainnet (default), testnet3, testnet4, signet
+//! or regtest.
 //!
 //!
+//!
+use btcx::params::{ChainParams, Mainnet, Regtest, Signet, Testnet3, Testnet4};
 use btcx::retarget::VerifyWithRetargetCircuit;
 use plonky2x::backend::function::Plonky2xFunction;
 
+fn entrypoint<P: ChainParams>(headers_count: usize) {
+    match headers_count {
+        10 => VerifyWithRetargetCircuit::<P, 10>::entrypoint(),
+        18 => VerifyWithRetargetCircuit::<P, 18>::entrypoint(),
+        36 => VerifyWithRetargetCircuit::<P, 36>::entrypoint(),
+        72 => VerifyWithRetargetCircuit::<P, 72>::entrypoint(),
+        144 => VerifyWithRetargetCircuit::<P, 144>::entrypoint(),
+        288 => VerifyWithRetargetCircuit::<P, 288>::entrypoint(),
+        576 => VerifyWithRetargetCircuit::<P, 576>::entrypoint(),
+        1008 => VerifyWithRetargetCircuit::<P, 1008>::entrypoint(),
+        2016 => VerifyWithRetargetCircuit::<P, 2016>::entrypoint(),
+        _ => panic!("Unsupported headers count"),
+    }
+}
+
 fn main() {
     let headers_count = std::env::var("UPDATE_HEADERS_COUNT")
         .unwrap()
         .parse()
         .unwrap();
 
-    match headers_count {
-        10 => VerifyWithRetargetCircuit::<10>::entrypoint(),
-        18 => VerifyWithRetargetCircuit::<18>::entrypoint(),
-        36 => VerifyWithRetargetCircuit::<36>::entrypoint(),
-        72 => VerifyWithRetargetCircuit::<72>::entrypoint(),
-        144 => VerifyWithRetargetCircuit::<144>::entrypoint(),
-        288 => VerifyWithRetargetCircuit::<288>::entrypoint(),
-        576 => VerifyWithRetargetCircuit::<576>::entrypoint(),
-        1008 => VerifyWithRetargetCircuit::<1008>::entrypoint(),
-        2016 => VerifyWithRetargetCircuit::<2016>::entrypoint(),
-        _ => panic!("Unsupported headers count"),
+    match std::env::var("NETWORK").as_deref().unwrap_or("mainnet") {
+        "mainnet" => entrypoint::<Mainnet>(headers_count),
+        "testnet3" => entrypoint::<Testnet3>(headers_count),
+        "testnet4" => entrypoint::<Testnet4>(headers_count),
+        "signet" => entrypoint::<Signet>(headers_count),
+        "regtest" => entrypoint::<Regtest>(headers_count),
+        _ => panic!("Unsupported network"),
     }
 }
@@ -0,0 +1,49 @@
+//! To build the binary:
+//!
+//!     `cargo build --release --bin retarget_bip34`
+//!
+//! To prove the circuit using evm io:
+//!
+//!    `./target/release/retarget_bip34 prove --input-json src/bin/circuit_function_evm_input.json`
+//!
+//! Note that this circuit will not work with field-based io.
+//!
+//! The network is selected with `NETWORK`: mainnet (default), testnet3, testnet4, signet
+//! or regtest.
+//!
+//!
+//!
+use btcx::params::{ChainParams, Mainnet, Regtest, Signet, Testnet3, Testnet4};
+use btcx::retarget::VerifyWithRetargetBip34Circuit;
+use plonky2x::backend::function::Plonky2xFunction;
+
+fn entrypoint<P: ChainParams>(headers_count: usize) {
+    match headers_count {
+        10 => VerifyWithRetargetBip34Circuit::<P, 10>::entrypoint(),
+        18 => VerifyWithRetargetBip34Circuit::<P, 18>::entrypoint(),
+        36 => VerifyWithRetargetBip34Circuit::<P, 36>::entrypoint(),
+        72 => VerifyWithRetargetBip34Circuit::<P, 72>::entrypoint(),
+        144 => VerifyWithRetargetBip34Circuit::<P, 144>::entrypoint(),
+        288 => VerifyWithRetargetBip34Circuit::<P, 288>::entrypoint(),
+        576 => VerifyWithRetargetBip34Circuit::<P, 576>::entrypoint(),
+        1008 => VerifyWithRetargetBip34Circuit::<P, 1008>::entrypoint(),
+        2016 => VerifyWithRetargetBip34Circuit::<P, 2016>::entrypoint(),
+        _ => panic!("Unsupported headers count"),
+    }
+}
+
+fn main() {
+    let headers_count = std::env::var("UPDATE_HEADERS_COUNT")
+        .unwrap()
+        .parse()
+        .unwrap();
+
+    match std::env::var("NETWORK").as_deref().unwrap_or("mainnet") {
+        "mainnet" => entrypoint::<Mainnet>(headers_count),
+        "testnet3" => entrypoint::<Testnet3>(headers_count),
+        "testnet4" => entrypoint::<Testnet4>(headers_count),
+        "signet" => entrypoint::<Signet>(headers_count),
+        "regtest" => entrypoint::<Regtest>(headers_count),
+        _ => panic!("Unsupported network"),
+    }
+}
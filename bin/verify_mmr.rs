@@ -0,0 +1,34 @@
+//! To build the binary:
+//!
+//!     `cargo build --release --bin verify_mmr`
+//!
+//! To prove the circuit using evm io:
+//!
+//!    `./target/release/verify_mmr prove --input-json src/bin/circuit_function_evm_input.json`
+//!
+//! Note that this circuit will not work with field-based io.
+//!
+//!
+//!
+use btcx::mmr::VerifyMmrCircuit;
+use plonky2x::backend::function::Plonky2xFunction;
+
+fn main() {
+    let headers_count = std::env::var("UPDATE_HEADERS_COUNT")
+        .unwrap()
+        .parse()
+        .unwrap();
+
+    match headers_count {
+        10 => VerifyMmrCircuit::<10>::entrypoint(),
+        18 => VerifyMmrCircuit::<18>::entrypoint(),
+        36 => VerifyMmrCircuit::<36>::entrypoint(),
+        72 => VerifyMmrCircuit::<72>::entrypoint(),
+        144 => VerifyMmrCircuit::<144>::entrypoint(),
+        288 => VerifyMmrCircuit::<288>::entrypoint(),
+        576 => VerifyMmrCircuit::<576>::entrypoint(),
+        1008 => VerifyMmrCircuit::<1008>::entrypoint(),
+        2016 => VerifyMmrCircuit::<2016>::entrypoint(),
+        _ => panic!("Unsupported headers count"),
+    }
+}
@@ -0,0 +1,25 @@
+//! To build the binary:
+//!
+//!     `cargo build --release --bin witness_inclusion`
+//!
+//! To prove the circuit using evm io:
+//!
+//!    `./target/release/witness_inclusion prove --input-json src/bin/circuit_function_evm_input.json`
+//!
+//! Note that this circuit will not work with field-based io.
+//!
+use btcx::tx::WitnessInclusionCircuit;
+use plonky2x::backend::function::Plonky2xFunction;
+
+fn main() {
+    let max_tx_bytes = std::env::var("MAX_TX_BYTES").unwrap().parse().unwrap();
+
+    match max_tx_bytes {
+        256 => WitnessInclusionCircuit::<256>::entrypoint(),
+        512 => WitnessInclusionCircuit::<512>::entrypoint(),
+        1024 => WitnessInclusionCircuit::<1024>::entrypoint(),
+        2048 => WitnessInclusionCircuit::<2048>::entrypoint(),
+        4096 => WitnessInclusionCircuit::<4096>::entrypoint(),
+        _ => panic!("Unsupported max transaction size"),
+    }
+}
@@ -11,6 +11,8 @@ use crate::vars::*;
 pub trait BitcoinHeaderVerify<L: PlonkParameters<D>, const D: usize> {
     fn calculate_hash(&mut self, header_bytes: &HeaderBytesVariable) -> BlockHashVariable;
 
+    fn get_version(&mut self, header: &HeaderBytesVariable) -> U32Variable;
+
     fn get_parent_hash(&mut self, header: &HeaderBytesVariable) -> BlockHashVariable;
 
     fn get_merkle_root(&mut self, header: &HeaderBytesVariable) -> Bytes32Variable;
@@ -19,6 +21,11 @@ pub trait BitcoinHeaderVerify<L: PlonkParameters<D>, const D: usize> {
 
     fn get_threshold(&mut self, header_bytes: &HeaderBytesVariable) -> ThresholdVariable;
 
+    fn decode_compact_target(
+        &mut self,
+        header_bytes: &HeaderBytesVariable,
+    ) -> (ThresholdVariable, BoolVariable, BoolVariable, BoolVariable);
+
     fn validate_threshold(
         &mut self,
         threshold: &ThresholdVariable,
@@ -34,6 +41,17 @@ impl<L: PlonkParameters<D>, const D: usize> BitcoinHeaderVerify<L, D> for Circui
         self.curta_sha256(&sha256_1.as_bytes())
     }
 
+    fn get_version(&mut self, header_bytes: &HeaderBytesVariable) -> U32Variable {
+        U32Variable::from_be_bits(
+            &header_bytes[HEADER_VERSION_INDEX..HEADER_VERSION_INDEX + 4]
+                .iter()
+                .rev()
+                .flat_map(|byte| byte.as_be_bits())
+                .collect::<Vec<_>>(),
+            self,
+        )
+    }
+
     fn get_parent_hash(&mut self, header_bytes: &HeaderBytesVariable) -> BlockHashVariable {
         header_bytes[HEADER_PARENT_HASH_INDEX..HEADER_PARENT_HASH_INDEX + 32]
             .try_into()
@@ -58,53 +76,98 @@ impl<L: PlonkParameters<D>, const D: usize> BitcoinHeaderVerify<L, D> for Circui
     }
 
     fn get_threshold(&mut self, header_bytes: &HeaderBytesVariable) -> ThresholdVariable {
-        // Extract difficulty exponent from header
+        let _false = self._false();
+
+        let _true = self._true();
+
+        let (threshold, is_negative, is_overflow, is_canonical) =
+            self.decode_compact_target(header_bytes);
+        self.assert_is_equal(is_negative, _false);
+        self.assert_is_equal(is_overflow, _false);
+        // consensus nBits always come from GetCompact, so each target has a single encoding
+        self.assert_is_equal(is_canonical, _true);
+
+        threshold
+    }
+
+    fn decode_compact_target(
+        &mut self,
+        header_bytes: &HeaderBytesVariable,
+    ) -> (ThresholdVariable, BoolVariable, BoolVariable, BoolVariable) {
+        // constants
+        let _false = self._false();
+        let _zero = self.zero::<ByteVariable>();
+        let sign_mask = self.constant::<ByteVariable>(0x7f);
+
+        // nBits is the size byte followed by the sign bit and the 23-bit word
         let difficulty_exp = U32Variable::from_be_bits(
             &header_bytes.index(HEADER_EXP_BYTE_INDEX).as_be_bits(),
             self,
         );
-
-        let const_32 = self.constant::<U32Variable>(32);
-        let const_1 = self.one::<U32Variable>();
-
-        let mantissa_index_1 = self.sub(const_32, difficulty_exp);
-        let mantissa_index_2 = self.add(const_1, mantissa_index_1);
-        let mantissa_index_3 = self.add(const_1, mantissa_index_2);
-
+        let is_sign_set = header_bytes[HEADER_MANTISSA_FIRST_BYTE_INDEX].as_be_bits()[0];
+        let word_bytes = [
+            self.and(header_bytes[HEADER_MANTISSA_FIRST_BYTE_INDEX], sign_mask),
+            header_bytes[HEADER_MANTISSA_SECOND_BYTE_INDEX],
+            header_bytes[HEADER_MANTISSA_THIRD_BYTE_INDEX],
+        ];
+
+        // word byte k lands at big-endian index 32 - exp + k, bytes shifted past either
+        // end of the 256 bits are dropped
         let mut threshold_bytes = Vec::<ByteVariable>::new();
 
         for j in 0..32 {
-            let const_index = self.constant::<U32Variable>(j as u32);
-
-            let is_first_mantissa_byte = self.is_equal(const_index, mantissa_index_1);
-            let is_second_mantissa_byte = self.is_equal(const_index, mantissa_index_2);
-            let is_third_mantissa_byte = self.is_equal(const_index, mantissa_index_3);
-
-            let mut threshold_byte = self.zero::<ByteVariable>();
-
-            threshold_byte = self.select(
-                is_first_mantissa_byte,
-                header_bytes[HEADER_MANTISSA_FIRST_BYTE_INDEX],
-                threshold_byte,
-            );
-            threshold_byte = self.select(
-                is_second_mantissa_byte,
-                header_bytes[HEADER_MANTISSA_SECOND_BYTE_INDEX],
-                threshold_byte,
-            );
-            threshold_byte = self.select(
-                is_third_mantissa_byte,
-                header_bytes[HEADER_MANTISSA_THIRD_BYTE_INDEX],
-                threshold_byte,
-            );
-
+            let mut threshold_byte = _zero;
+            for (k, word_byte) in word_bytes.iter().enumerate() {
+                let exp_at_index = self.constant::<U32Variable>((32 + k - j) as u32);
+                let is_at_index = self.is_equal(difficulty_exp, exp_at_index);
+                threshold_byte = self.select(is_at_index, *word_byte, threshold_byte);
+            }
             threshold_bytes.push(threshold_byte);
         }
 
-        Bytes32Variable(BytesVariable(
+        let threshold = Bytes32Variable(BytesVariable(
             threshold_bytes.as_slice().try_into().unwrap(),
         ))
-        .as_u256(self)
+        .as_u256(self);
+
+        // the flags only consider the word bytes kept for exponents below 3, and the target
+        // overflows if any nonzero byte is shifted above 256 bits
+        let mut is_word_nonzero = _false;
+        let mut is_overflow = _false;
+        let mut is_truncated = _false;
+        for (k, word_byte) in word_bytes.iter().enumerate() {
+            let is_zero = self.is_equal(*word_byte, _zero);
+            let is_nonzero = self.not(is_zero);
+
+            let min_exp = self.constant::<U32Variable>(k as u32);
+            let is_kept = self.lt(min_exp, difficulty_exp);
+            let is_kept_nonzero = self.and(is_nonzero, is_kept);
+            is_word_nonzero = self.or(is_word_nonzero, is_kept_nonzero);
+
+            let is_dropped = self.not(is_kept);
+            let is_truncated_byte = self.and(is_nonzero, is_dropped);
+            is_truncated = self.or(is_truncated, is_truncated_byte);
+
+            let max_exp = self.constant::<U32Variable>(32 + k as u32);
+            let is_shifted_out = self.lt(max_exp, difficulty_exp);
+            let is_overflow_byte = self.and(is_nonzero, is_shifted_out);
+            is_overflow = self.or(is_overflow, is_overflow_byte);
+        }
+        let is_negative = self.and(is_sign_set, is_word_nonzero);
+
+        // canonical encodings, as returned by GetCompact for a nonzero target, have the sign
+        // bit clear, a first word byte that is only zero if the next one would set the sign
+        // bit, and no nonzero byte dropped below the size
+        let is_first_byte_zero = self.is_equal(word_bytes[0], _zero);
+        let is_first_byte_set = self.not(is_first_byte_zero);
+        let is_second_byte_high = word_bytes[1].as_be_bits()[0];
+        let is_leading_byte_set = self.or(is_first_byte_set, is_second_byte_high);
+        let is_sign_clear = self.not(is_sign_set);
+        let is_not_truncated = self.not(is_truncated);
+        let is_canonical = self.and(is_sign_clear, is_leading_byte_set);
+        let is_canonical = self.and(is_canonical, is_not_truncated);
+
+        (threshold, is_negative, is_overflow, is_canonical)
     }
 
     fn validate_threshold(
@@ -135,6 +198,9 @@ impl<L: PlonkParameters<D>, const D: usize> BitcoinHeaderVerify<L, D> for Circui
         let is_valid = self.validate_threshold(&threshold, hash);
         self.assert_is_equal(is_valid, _true);
 
+        // version
+        let version = self.get_version(&header_bytes);
+
         // parent hash
         let parent_hash = self.get_parent_hash(&header_bytes);
 
@@ -146,6 +212,7 @@ impl<L: PlonkParameters<D>, const D: usize> BitcoinHeaderVerify<L, D> for Circui
 
         // return hash & work
         BitcoinHeaderVariable {
+            version,
             hash,
             parent_hash,
             merkle_root,
@@ -203,10 +270,11 @@ mod test {
             bytes32!("0xfe0c7ab1158d234b8109d23004770f907ce86dd2602600000000000000000000");
         let expected_merkle_root =
             bytes32!("0xd4a3d278e4427cd05d83889eee4a74e0d8e88d29b580cd4af081eeca3e5e9be1");
+        let expected_version = 0x3150c000;
         let expected_timestamp = 1701860856;
         let (exp, mantissa) = compute_exp_and_mantissa(header_input);
-        let expected_threshold =
-            U256::from_little_endian(compute_threshold(exp, mantissa).to_bytes_le().as_slice());
+        let (threshold, _, _, _) = compute_threshold(exp, mantissa);
+        let expected_threshold = U256::from_little_endian(threshold.to_bytes_le().as_slice());
 
         log::debug!("Hash: {:?} = {:?}", header.hash, expected_hash);
         log::debug!(
@@ -230,10 +298,72 @@ mod test {
             expected_threshold
         );
 
+        assert_eq!(header.version, expected_version);
         assert_eq!(header.hash, expected_hash);
         assert_eq!(header.parent_hash, expected_parent_hash);
         assert_eq!(header.merkle_root, expected_merkle_root);
         assert_eq!(header.timestamp, expected_timestamp);
         assert_eq!(header.threshold, expected_threshold);
     }
+
+    #[test]
+    fn test_decode_compact_target() {
+        env::set_var("RUST_LOG", "debug");
+        env_logger::try_init().unwrap_or_default();
+
+        let mut builder = DefaultBuilder::new();
+        let header_bytes = builder.read::<HeaderBytesVariable>();
+        let (threshold, is_negative, is_overflow, is_canonical) =
+            builder.decode_compact_target(&header_bytes);
+        builder.write(threshold);
+        builder.write(is_negative);
+        builder.write(is_overflow);
+        builder.write(is_canonical);
+        let circuit = builder.build();
+
+        // sizes below 3, set sign bits, non-canonical words and sizes above 32
+        for bits in [
+            0x00000000u32,
+            0x01003456,
+            0x01120000,
+            0x01fedcba,
+            0x02008000,
+            0x02123456,
+            0x03123400,
+            0x04001234,
+            0x04923456,
+            0x05009234,
+            0x1d00ffff,
+            0x20123456,
+            0x21010000,
+            0x22000001,
+            0xff123456,
+        ] {
+            let mut header_input = [0u8; HEADER_BYTES_LENGTH];
+            header_input[HEADER_MANTISSA_THIRD_BYTE_INDEX..HEADER_EXP_BYTE_INDEX + 1]
+                .copy_from_slice(&bits.to_le_bytes());
+
+            let mut input = circuit.input();
+            input.write::<HeaderBytesVariable>(header_input);
+
+            log::debug!("Generating circuit proof for bits {:#010x}", bits);
+            let (proof, mut output) = circuit.prove(&input);
+            circuit.verify(&proof, &input, &output);
+
+            let (exp, mantissa) = compute_exp_and_mantissa(header_input);
+            let (
+                expected_threshold,
+                expected_is_negative,
+                expected_is_overflow,
+                expected_is_canonical,
+            ) = compute_threshold(exp, mantissa);
+            assert_eq!(
+                output.read::<ThresholdVariable>(),
+                U256::from_little_endian(expected_threshold.to_bytes_le().as_slice())
+            );
+            assert_eq!(output.read::<BoolVariable>(), expected_is_negative);
+            assert_eq!(output.read::<BoolVariable>(), expected_is_overflow);
+            assert_eq!(output.read::<BoolVariable>(), expected_is_canonical);
+        }
+    }
 }
@@ -0,0 +1,285 @@
+use ethers::types::H256;
+use plonky2x::frontend::vars::EvmVariable;
+use plonky2x::prelude::{
+    ArrayVariable, Bytes32Variable, CircuitBuilder, PlonkParameters, U32Variable,
+};
+
+use crate::consts::MMR_MAX_PEAKS;
+use crate::vars::*;
+
+pub trait BitcoinMmr<L: PlonkParameters<D>, const D: usize> {
+    fn mmr_merge(&mut self, left: &Bytes32Variable, right: &Bytes32Variable) -> Bytes32Variable;
+
+    fn mmr_root(&mut self, size: &U32Variable, peaks: &MmrPeaksVariable) -> Bytes32Variable;
+
+    fn mmr_append<const LEAVES_COUNT: usize>(
+        &mut self,
+        size: &U32Variable,
+        peaks: &MmrPeaksVariable,
+        leaves: &ArrayVariable<Bytes32Variable, LEAVES_COUNT>,
+    ) -> (U32Variable, MmrPeaksVariable);
+
+    fn mmr_verify_inclusion(
+        &mut self,
+        size: &U32Variable,
+        peaks: &MmrPeaksVariable,
+        position: &U32Variable,
+        leaf: &Bytes32Variable,
+        proof: &MmrProofVariable,
+    );
+}
+
+impl<L: PlonkParameters<D>, const D: usize> BitcoinMmr<L, D> for CircuitBuilder<L, D> {
+    fn mmr_merge(&mut self, left: &Bytes32Variable, right: &Bytes32Variable) -> Bytes32Variable {
+        self.curta_sha256(&[left.as_bytes(), right.as_bytes()].concat())
+    }
+
+    fn mmr_root(&mut self, size: &U32Variable, peaks: &MmrPeaksVariable) -> Bytes32Variable {
+        let mut bytes = size.encode(self);
+        for peak in peaks.as_vec() {
+            bytes.extend(peak.as_bytes());
+        }
+        self.curta_sha256(&bytes)
+    }
+
+    fn mmr_append<const LEAVES_COUNT: usize>(
+        &mut self,
+        size: &U32Variable,
+        peaks: &MmrPeaksVariable,
+        leaves: &ArrayVariable<Bytes32Variable, LEAVES_COUNT>,
+    ) -> (U32Variable, MmrPeaksVariable) {
+        // constants
+        let _zero = self.constant::<Bytes32Variable>(H256::zero());
+        let _one = self.one::<U32Variable>();
+        let _two = self.constant::<U32Variable>(2);
+
+        let leaves_count = self.constant::<U32Variable>(LEAVES_COUNT as u32);
+        let new_size = self.add(*size, leaves_count);
+
+        let size_bits = self.to_be_bits(*size);
+        let new_size_bits = self.to_be_bits(new_size);
+
+        // merge the leaves level by level instead of leaf by leaf, so that the number of
+        // hashes is bounded by LEAVES_COUNT + MMR_MAX_PEAKS for any prior size
+        let mut nodes = leaves.as_vec();
+        let mut nodes_count = leaves_count;
+        let mut new_peaks: Vec<Bytes32Variable> = Vec::new();
+
+        for h in 0..MMR_MAX_PEAKS {
+            let has_peak = size_bits[MMR_MAX_PEAKS - 1 - h];
+            let has_new_peak = new_size_bits[MMR_MAX_PEAKS - 1 - h];
+
+            // the prior peak covers older leaves, so it precedes the nodes of this level
+            let level = (0..=nodes.len())
+                .map(|k| {
+                    let shifted = if k == 0 { peaks[h] } else { nodes[k - 1] };
+                    if k < nodes.len() {
+                        self.select(has_peak, shifted, nodes[k])
+                    } else {
+                        shifted
+                    }
+                })
+                .collect::<Vec<_>>();
+            let nodes_with_peak = self.add(nodes_count, _one);
+            let level_count = self.select(has_peak, nodes_with_peak, nodes_count);
+
+            // a node left over after pairing becomes the new peak of this height
+            let mut new_peak = _zero;
+            for (k, node) in level.iter().enumerate() {
+                let position = self.constant::<U32Variable>(k as u32 + 1);
+                let is_last = self.is_equal(position, level_count);
+                new_peak = self.select(is_last, *node, new_peak);
+            }
+            new_peaks.push(self.select(has_new_peak, new_peak, _zero));
+
+            // pair up the nodes for the next level
+            nodes = level
+                .chunks_exact(2)
+                .map(|pair| self.mmr_merge(&pair[0], &pair[1]))
+                .collect();
+            nodes_count = self.div(level_count, _two);
+        }
+
+        (new_size, ArrayVariable::from(new_peaks))
+    }
+
+    fn mmr_verify_inclusion(
+        &mut self,
+        size: &U32Variable,
+        peaks: &MmrPeaksVariable,
+        position: &U32Variable,
+        leaf: &Bytes32Variable,
+        proof: &MmrProofVariable,
+    ) {
+        let _true = self._true();
+        let _false = self._false();
+        let _zero = self.constant::<Bytes32Variable>(H256::zero());
+
+        // the fold below only reads the position bits under the peak, so higher bits could
+        // otherwise claim any position
+        let is_in_range = self.lt(*position, *size);
+        self.assert_is_equal(is_in_range, _true);
+
+        let size_bits = self.to_be_bits(*size);
+        let position_bits = self.to_be_bits(*position);
+
+        // as position < size, the leaf is in the peak of the highest height h where the size
+        // has a peak and the position does not, the highest bit where the two differ
+        let mut is_below_peak = vec![_false; MMR_MAX_PEAKS];
+        let mut is_peak = vec![_false; MMR_MAX_PEAKS];
+        let mut has_peak_above = _false;
+        for h in (0..MMR_MAX_PEAKS).rev() {
+            is_below_peak[h] = has_peak_above;
+
+            let position_bit = self.not(position_bits[MMR_MAX_PEAKS - 1 - h]);
+            let is_candidate = self.and(size_bits[MMR_MAX_PEAKS - 1 - h], position_bit);
+            let is_first_candidate = self.not(has_peak_above);
+            is_peak[h] = self.and(is_candidate, is_first_candidate);
+            has_peak_above = self.or(has_peak_above, is_candidate);
+        }
+        self.assert_is_equal(has_peak_above, _true);
+
+        // fold the proof up to the peak, the position bits give the side of each node
+        let mut node = *leaf;
+        let mut peak = _zero;
+        for h in 0..MMR_MAX_PEAKS {
+            peak = self.select(is_peak[h], peaks[h], peak);
+
+            let is_right = position_bits[MMR_MAX_PEAKS - 1 - h];
+            let left = self.select(is_right, proof[h], node);
+            let right = self.select(is_right, node, proof[h]);
+            let parent = self.mmr_merge(&left, &right);
+            node = self.select(is_below_peak[h], parent, node);
+        }
+        self.assert_is_equal(peak, node);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+
+    use plonky2x::prelude::DefaultBuilder;
+
+    use super::*;
+    use crate::input::mmr::HeaderMmr;
+
+    #[test]
+    fn test_mmr_append() {
+        env::set_var("RUST_LOG", "debug");
+        env_logger::try_init().unwrap_or_default();
+
+        const LEAVES_COUNT: usize = 6;
+
+        log::debug!("Defining circuit");
+        let mut builder = DefaultBuilder::new();
+
+        let size = builder.read::<U32Variable>();
+        let peaks = builder.read::<MmrPeaksVariable>();
+        let leaves = builder.read::<ArrayVariable<Bytes32Variable, LEAVES_COUNT>>();
+
+        let (new_size, new_peaks) = builder.mmr_append(&size, &peaks, &leaves);
+        let new_root = builder.mmr_root(&new_size, &new_peaks);
+        builder.write(new_size);
+        builder.write(new_root);
+
+        log::debug!("Building circuit");
+        let circuit = builder.build();
+        log::debug!("Done building circuit");
+
+        // a prior size of 0b1011 exercises carries through existing peaks
+        let mut mmr = HeaderMmr::default();
+        for i in 0..11u8 {
+            mmr.append(H256::repeat_byte(i + 1));
+        }
+        let leaves_values = (0..LEAVES_COUNT as u8)
+            .map(|i| H256::repeat_byte(0x80 + i))
+            .collect::<Vec<_>>();
+
+        let mut input = circuit.input();
+        input.write::<U32Variable>(mmr.size);
+        input.write::<MmrPeaksVariable>(mmr.peaks.to_vec());
+        input.write::<ArrayVariable<Bytes32Variable, LEAVES_COUNT>>(leaves_values.clone());
+
+        log::debug!("Generating circuit proof");
+        let (proof, mut output) = circuit.prove(&input);
+        circuit.verify(&proof, &input, &output);
+
+        for leaf in leaves_values {
+            mmr.append(leaf);
+        }
+        assert_eq!(output.read::<U32Variable>(), mmr.size);
+        assert_eq!(output.read::<Bytes32Variable>(), mmr.root());
+    }
+
+    #[test]
+    fn test_mmr_verify_inclusion() {
+        env::set_var("RUST_LOG", "debug");
+        env_logger::try_init().unwrap_or_default();
+
+        log::debug!("Defining circuit");
+        let mut builder = DefaultBuilder::new();
+
+        let size = builder.read::<U32Variable>();
+        let peaks = builder.read::<MmrPeaksVariable>();
+        let position = builder.read::<U32Variable>();
+        let leaf = builder.read::<Bytes32Variable>();
+        let proof = builder.read::<MmrProofVariable>();
+        builder.mmr_verify_inclusion(&size, &peaks, &position, &leaf, &proof);
+
+        log::debug!("Building circuit");
+        let circuit = builder.build();
+        log::debug!("Done building circuit");
+
+        let leaves = (0..11u8)
+            .map(|i| H256::repeat_byte(i + 1))
+            .collect::<Vec<_>>();
+        let mmr = HeaderMmr::from_leaves(&leaves);
+
+        // positions in the peaks of height 3, 1 and 0
+        for position in [5, 9, 10] {
+            let mut input = circuit.input();
+            input.write::<U32Variable>(mmr.size);
+            input.write::<MmrPeaksVariable>(mmr.peaks.to_vec());
+            input.write::<U32Variable>(position);
+            input.write::<Bytes32Variable>(leaves[position as usize]);
+            input.write::<MmrProofVariable>(HeaderMmr::inclusion_proof(&leaves, position).to_vec());
+
+            log::debug!("Generating circuit proof for position {}", position);
+            let (proof, output) = circuit.prove(&input);
+            circuit.verify(&proof, &input, &output);
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_mmr_verify_inclusion_out_of_range() {
+        env::set_var("RUST_LOG", "debug");
+        env_logger::try_init().unwrap_or_default();
+
+        let mut builder = DefaultBuilder::new();
+        let size = builder.read::<U32Variable>();
+        let peaks = builder.read::<MmrPeaksVariable>();
+        let position = builder.read::<U32Variable>();
+        let leaf = builder.read::<Bytes32Variable>();
+        let proof = builder.read::<MmrProofVariable>();
+        builder.mmr_verify_inclusion(&size, &peaks, &position, &leaf, &proof);
+        let circuit = builder.build();
+
+        let leaves = (0..4u8)
+            .map(|i| H256::repeat_byte(i + 1))
+            .collect::<Vec<_>>();
+        let mmr = HeaderMmr::from_leaves(&leaves);
+
+        // 11 = 0b1011 shares its low bits with leaf 3, whose proof would otherwise verify
+        let mut input = circuit.input();
+        input.write::<U32Variable>(mmr.size);
+        input.write::<MmrPeaksVariable>(mmr.peaks.to_vec());
+        input.write::<U32Variable>(11);
+        input.write::<Bytes32Variable>(leaves[3]);
+        input.write::<MmrProofVariable>(HeaderMmr::inclusion_proof(&leaves, 3).to_vec());
+
+        let (proof, output) = circuit.prove(&input);
+        circuit.verify(&proof, &input, &output);
+    }
+}
@@ -1,2 +1,4 @@
 pub mod header;
+pub mod mmr;
 pub mod multi;
+pub mod tx;
@@ -1,9 +1,13 @@
+use ethers::types::{H256, U256};
 use plonky2x::prelude::{
-    ArrayVariable, CircuitBuilder, PlonkParameters, U256Variable, U32Variable, U64Variable,
+    ArrayVariable, BoolVariable, CircuitBuilder, PlonkParameters, U256Variable, U32Variable,
+    U64Variable,
 };
 
 use crate::builder::header::BitcoinHeaderVerify;
-use crate::utils::u256_from_gen;
+use crate::consts::{MAX_TIMEWARP, MEDIAN_TIME_SPAN};
+use crate::params::{ChainParams, VersionActivations};
+use crate::utils::{compute_work, u256_from_gen};
 use crate::vars::*;
 
 pub trait BitcoinMultiVerify<L: PlonkParameters<D>, const D: usize> {
@@ -11,10 +15,16 @@ pub trait BitcoinMultiVerify<L: PlonkParameters<D>, const D: usize> {
         &mut self,
         prev_header_hash: &BlockHashVariable,
         threshold: &ThresholdVariable,
+        max_timestamp: &U32Variable,
+        context_headers_bytes: &TimestampContextVariable,
         update_headers_bytes: &ArrayVariable<HeaderBytesVariable, UPDATE_HEADERS_COUNT>,
-    ) -> ArrayVariable<BlockHashVariable, UPDATE_HEADERS_COUNT>;
+    ) -> (
+        ArrayVariable<BlockHashVariable, UPDATE_HEADERS_COUNT>,
+        WorkVariable,
+        U32Variable,
+    );
 
-    fn validate_headers_with_retargeting<const UPDATE_HEADERS_COUNT: usize>(
+    fn validate_headers_with_retargeting<P: ChainParams, const UPDATE_HEADERS_COUNT: usize>(
         &mut self,
         prev_block_number: &U64Variable,
         prev_header_hash: &BlockHashVariable,
@@ -23,15 +33,64 @@ pub trait BitcoinMultiVerify<L: PlonkParameters<D>, const D: usize> {
         next_threshold: &ThresholdVariable,
         period_start_header_bytes: &HeaderBytesVariable,
         period_end_header_bytes: &HeaderBytesVariable,
+        max_timestamp: &U32Variable,
+        context_headers_bytes: &TimestampContextVariable,
         update_headers_bytes: &ArrayVariable<HeaderBytesVariable, UPDATE_HEADERS_COUNT>,
-    ) -> (ArrayVariable<BlockHashVariable, UPDATE_HEADERS_COUNT>, ThresholdVariable);
+    ) -> (
+        ArrayVariable<BlockHashVariable, UPDATE_HEADERS_COUNT>,
+        ThresholdVariable,
+        WorkVariable,
+        U32Variable,
+    );
+
+    fn validate_timestamp_context(
+        &mut self,
+        prev_header_hash: &BlockHashVariable,
+        context_headers_bytes: &TimestampContextVariable,
+    ) -> (Vec<U32Variable>, U32Variable);
+
+    fn is_after_median_time_past(
+        &mut self,
+        timestamps: &[U32Variable],
+        timestamps_count: &U32Variable,
+        timestamp: &U32Variable,
+    ) -> BoolVariable;
+
+    fn median_time_past(
+        &mut self,
+        timestamps: &[U32Variable],
+        timestamps_count: &U32Variable,
+    ) -> U32Variable;
+
+    fn push_timestamp_count(&mut self, timestamps_count: &U32Variable) -> U32Variable;
 
-    fn adjust_threshold(
+    fn max_timestamp(
+        &mut self,
+        reference_time: &U32Variable,
+        max_future_block_time: &U32Variable,
+    ) -> U32Variable;
+
+    fn is_min_difficulty_allowed<P: ChainParams>(
+        &mut self,
+        parent_timestamp: &U32Variable,
+        timestamp: &U32Variable,
+    ) -> BoolVariable;
+
+    fn is_valid_version(
+        &mut self,
+        version: &U32Variable,
+        height: &U64Variable,
+        activations: &VersionActivations,
+    ) -> BoolVariable;
+
+    fn adjust_threshold<P: ChainParams>(
         &mut self,
         threshold: &ThresholdVariable,
         period_start_timestamp: U32Variable,
         period_end_timestamp: U32Variable,
     ) -> U256Variable;
+
+    fn compute_work(&mut self, threshold: &ThresholdVariable) -> WorkVariable;
 }
 
 impl<L: PlonkParameters<D>, const D: usize> BitcoinMultiVerify<L, D> for CircuitBuilder<L, D> {
@@ -39,10 +98,25 @@ impl<L: PlonkParameters<D>, const D: usize> BitcoinMultiVerify<L, D> for Circuit
         &mut self,
         prev_header_hash: &BlockHashVariable,
         threshold: &ThresholdVariable,
+        max_timestamp: &U32Variable,
+        context_headers_bytes: &TimestampContextVariable,
         update_headers_bytes: &ArrayVariable<HeaderBytesVariable, UPDATE_HEADERS_COUNT>,
-    ) -> ArrayVariable<BlockHashVariable, UPDATE_HEADERS_COUNT> {
+    ) -> (
+        ArrayVariable<BlockHashVariable, UPDATE_HEADERS_COUNT>,
+        WorkVariable,
+        U32Variable,
+    ) {
+        let _true = self._true();
         let mut hashes: Vec<BlockHashVariable> = Vec::new();
 
+        // timestamps of the last MEDIAN_TIME_SPAN headers, oldest first
+        let (mut timestamps, mut timestamps_count) =
+            self.validate_timestamp_context(prev_header_hash, context_headers_bytes);
+
+        // all headers share the same threshold and therefore the same work
+        let work = self.compute_work(threshold);
+        let mut total_work = self.zero::<WorkVariable>();
+
         for h in 0..UPDATE_HEADERS_COUNT {
             let header = self.validate_header(&update_headers_bytes[h]);
 
@@ -56,13 +130,26 @@ impl<L: PlonkParameters<D>, const D: usize> BitcoinMultiVerify<L, D> for Circuit
                 header.parent_hash,
             );
 
+            // validate timestamp against the median of the previous headers and the upper bound
+            let is_after_mtp =
+                self.is_after_median_time_past(&timestamps, &timestamps_count, &header.timestamp);
+            self.assert_is_equal(is_after_mtp, _true);
+            let is_within_max_timestamp = self.lte(header.timestamp, *max_timestamp);
+            self.assert_is_equal(is_within_max_timestamp, _true);
+            timestamps.remove(0);
+            timestamps.push(header.timestamp);
+            timestamps_count = self.push_timestamp_count(&timestamps_count);
+
             hashes.push(header.hash);
+            total_work = self.add(total_work, work);
         }
 
-        ArrayVariable::from(hashes)
+        let median_time_past = self.median_time_past(&timestamps, &timestamps_count);
+
+        (ArrayVariable::from(hashes), total_work, median_time_past)
     }
 
-    fn validate_headers_with_retargeting<const UPDATE_HEADERS_COUNT: usize>(
+    fn validate_headers_with_retargeting<P: ChainParams, const UPDATE_HEADERS_COUNT: usize>(
         &mut self,
         prev_block_number: &U64Variable,
         prev_header_hash: &BlockHashVariable,
@@ -71,13 +158,27 @@ impl<L: PlonkParameters<D>, const D: usize> BitcoinMultiVerify<L, D> for Circuit
         next_threshold: &ThresholdVariable,
         period_start_header_bytes: &HeaderBytesVariable,
         period_end_header_bytes: &HeaderBytesVariable,
+        max_timestamp: &U32Variable,
+        context_headers_bytes: &TimestampContextVariable,
         update_headers_bytes: &ArrayVariable<HeaderBytesVariable, UPDATE_HEADERS_COUNT>,
-    ) -> (ArrayVariable<BlockHashVariable, UPDATE_HEADERS_COUNT>, ThresholdVariable) {
+    ) -> (
+        ArrayVariable<BlockHashVariable, UPDATE_HEADERS_COUNT>,
+        ThresholdVariable,
+        WorkVariable,
+        U32Variable,
+    ) {
         // constants
         let _true = self._true();
         let _zero = self.zero::<U64Variable>();
         let _one = self.one::<U64Variable>();
-        let retarget_window = self.constant::<U64Variable>(2016);
+        let retarget_window = self.constant::<U64Variable>(P::DIFFICULTY_ADJUSTMENT_INTERVAL);
+        let min_difficulty_target = P::min_difficulty_target();
+        let min_difficulty_work = self.constant::<WorkVariable>(U256::from_big_endian(
+            &compute_work(min_difficulty_target.clone()).to_bytes_be(),
+        ));
+        let min_difficulty_threshold = self.constant::<ThresholdVariable>(U256::from_big_endian(
+            &min_difficulty_target.to_bytes_be(),
+        ));
 
         // calculate index of the first block in the next period after retargeting
         let first_bn_in_seq = self.add(*prev_block_number, _one);
@@ -104,11 +205,27 @@ impl<L: PlonkParameters<D>, const D: usize> BitcoinMultiVerify<L, D> for Circuit
         let period_end_header_hash =
             self.select(not_in_seq, *prev_header_hash, period_end_header.hash);
         self.assert_is_equal(period_end_header_hash, period_end_header.hash);
-        self.assert_is_equal(*current_threshold, period_end_header.threshold);
+        if P::POW_ALLOW_MIN_DIFFICULTY_BLOCKS {
+            // the period may end with a minimum difficulty block
+            let is_current_threshold =
+                self.is_equal(*current_threshold, period_end_header.threshold);
+            let is_min_difficulty =
+                self.is_equal(min_difficulty_threshold, period_end_header.threshold);
+            let is_valid_threshold = self.or(is_current_threshold, is_min_difficulty);
+            self.assert_is_equal(is_valid_threshold, _true);
+        } else {
+            self.assert_is_equal(*current_threshold, period_end_header.threshold);
+        }
 
-        // retarget threshold
-        let next_threshold_adjusted: U256Variable = self.adjust_threshold(
-            current_threshold,
+        // retarget threshold, from the target of the last block of the period like Bitcoin
+        // Core or from the first one with BIP94
+        let retarget_base_threshold = if P::ENFORCE_BIP94 {
+            *current_threshold
+        } else {
+            period_end_header.threshold
+        };
+        let next_threshold_adjusted: U256Variable = self.adjust_threshold::<P>(
+            &retarget_base_threshold,
             period_start_header.timestamp,
             period_end_header.timestamp,
         );
@@ -124,8 +241,15 @@ impl<L: PlonkParameters<D>, const D: usize> BitcoinMultiVerify<L, D> for Circuit
         });
         self.assert_is_equal(*next_threshold, next_threshold_refined);
 
+        // work of a single header in each period
+        let current_work = self.compute_work(current_threshold);
+        let next_work = self.compute_work(next_threshold);
+        let mut total_work = self.zero::<WorkVariable>();
+
         // validate headers
         let mut hashes: Vec<BlockHashVariable> = Vec::new();
+        let (mut timestamps, mut timestamps_count) =
+            self.validate_timestamp_context(prev_header_hash, context_headers_bytes);
 
         for i in 0..UPDATE_HEADERS_COUNT {
             let index = self.constant::<U64Variable>(i as u64);
@@ -133,10 +257,33 @@ impl<L: PlonkParameters<D>, const D: usize> BitcoinMultiVerify<L, D> for Circuit
 
             let header = self.validate_header(&update_headers_bytes[i]);
 
+            // validate version against the soft forks active at the header's height
+            let height = self.add(first_bn_in_seq, index);
+            let is_valid_version =
+                self.is_valid_version(&header.version, &height, &P::VERSION_ACTIVATIONS);
+            self.assert_is_equal(is_valid_version, _true);
+
             // validate threshold
-            let threshold = self.select(is_in_prev_period, *current_threshold, *next_threshold);
+            let mut threshold = self.select(is_in_prev_period, *current_threshold, *next_threshold);
+            let mut work = self.select(is_in_prev_period, current_work, next_work);
+            if P::POW_ALLOW_MIN_DIFFICULTY_BLOCKS {
+                // a late block takes the minimum difficulty, except at the start of a period;
+                // the other blocks keep the target of the period, which is where walking back
+                // past the minimum difficulty blocks ends
+                let parent_timestamp = timestamps[MEDIAN_TIME_SPAN - 1];
+                let is_late =
+                    self.is_min_difficulty_allowed::<P>(&parent_timestamp, &header.timestamp);
+                let is_period_start = self.is_equal(index, new_period_start_header_index);
+                let is_within_period = self.not(is_period_start);
+                let is_min_difficulty = self.and(is_late, is_within_period);
+                threshold = self.select(is_min_difficulty, min_difficulty_threshold, threshold);
+                work = self.select(is_min_difficulty, min_difficulty_work, work);
+            }
             self.assert_is_equal(threshold, header.threshold);
 
+            // accumulate work
+            total_work = self.add(total_work, work);
+
             // validate parent hash
             self.assert_is_equal(
                 if i == 0 {
@@ -147,6 +294,30 @@ impl<L: PlonkParameters<D>, const D: usize> BitcoinMultiVerify<L, D> for Circuit
                 header.parent_hash,
             );
 
+            // validate timestamp against the median of the previous headers and the upper bound
+            let is_after_mtp =
+                self.is_after_median_time_past(&timestamps, &timestamps_count, &header.timestamp);
+            self.assert_is_equal(is_after_mtp, _true);
+            let is_within_max_timestamp = self.lte(header.timestamp, *max_timestamp);
+            self.assert_is_equal(is_within_max_timestamp, _true);
+            if P::ENFORCE_BIP94 {
+                // the BIP94 timewarp rule: the first block of a period may be at most
+                // MAX_TIMEWARP seconds earlier than its parent
+                let parent_timestamp = timestamps[MEDIAN_TIME_SPAN - 1];
+                let max_timewarp = self.constant::<U32Variable>(MAX_TIMEWARP);
+                let is_not_earlier = self.lte(parent_timestamp, header.timestamp);
+                let timewarp = self.sub(parent_timestamp, header.timestamp);
+                let is_within_timewarp = self.lte(timewarp, max_timewarp);
+                let is_valid_timestamp = self.or(is_not_earlier, is_within_timewarp);
+                let is_period_start = self.is_equal(index, new_period_start_header_index);
+                let is_within_period = self.not(is_period_start);
+                let is_valid_timestamp = self.or(is_within_period, is_valid_timestamp);
+                self.assert_is_equal(is_valid_timestamp, _true);
+            }
+            timestamps.remove(0);
+            timestamps.push(header.timestamp);
+            timestamps_count = self.push_timestamp_count(&timestamps_count);
+
             // validate period end header (in case if it's in the sequence)
             let next_index = self.add(index, _one);
             let is_last_in_prev_period = self.is_equal(next_index, new_period_start_header_index);
@@ -155,27 +326,203 @@ impl<L: PlonkParameters<D>, const D: usize> BitcoinMultiVerify<L, D> for Circuit
             hashes.push(hash);
         }
 
-        (ArrayVariable::from(hashes), next_threshold_refined)
+        let median_time_past = self.median_time_past(&timestamps, &timestamps_count);
+
+        (
+            ArrayVariable::from(hashes),
+            next_threshold_refined,
+            total_work,
+            median_time_past,
+        )
+    }
+
+    fn validate_timestamp_context(
+        &mut self,
+        prev_header_hash: &BlockHashVariable,
+        context_headers_bytes: &TimestampContextVariable,
+    ) -> (Vec<U32Variable>, U32Variable) {
+        // constants
+        let _zero = self.zero::<U32Variable>();
+        let _one = self.one::<U32Variable>();
+        let _zero_hash = self.constant::<BlockHashVariable>(H256::zero());
+
+        // walk back from the previous header; headers before genesis are zero-padded and
+        // left out of the count, so that the median only covers the existing ancestors
+        let mut timestamps = vec![_zero; MEDIAN_TIME_SPAN];
+        let mut timestamps_count = _zero;
+        let mut is_before_genesis = self._false();
+        let mut expected_hash = *prev_header_hash;
+        for i in (0..MEDIAN_TIME_SPAN).rev() {
+            let hash = self.calculate_hash(&context_headers_bytes[i]);
+            let linked_hash = self.select(is_before_genesis, hash, expected_hash);
+            self.assert_is_equal(hash, linked_hash);
+
+            let timestamp = self.get_timestamp(&context_headers_bytes[i]);
+            timestamps[i] = self.select(is_before_genesis, _zero, timestamp);
+            let increment = self.select(is_before_genesis, _zero, _one);
+            timestamps_count = self.add(timestamps_count, increment);
+
+            expected_hash = self.get_parent_hash(&context_headers_bytes[i]);
+            let is_genesis = self.is_equal(expected_hash, _zero_hash);
+            is_before_genesis = self.or(is_before_genesis, is_genesis);
+        }
+
+        (timestamps, timestamps_count)
+    }
+
+    /// Only the last `timestamps_count` timestamps count, as earlier ones are before genesis.
+    /// Like Bitcoin Core, the timestamp is above the median if it is above more than
+    /// `timestamps_count / 2` of them, i.e. if twice their number exceeds the count.
+    fn is_after_median_time_past(
+        &mut self,
+        timestamps: &[U32Variable],
+        timestamps_count: &U32Variable,
+        timestamp: &U32Variable,
+    ) -> BoolVariable {
+        let _zero = self.zero::<U32Variable>();
+        let _one = self.one::<U32Variable>();
+
+        let mut below_count = _zero;
+        for (i, t) in timestamps.iter().enumerate() {
+            let min_count = self.constant::<U32Variable>((timestamps.len() - i) as u32);
+            let is_present = self.lte(min_count, *timestamps_count);
+            let is_below = self.lt(*t, *timestamp);
+            let is_below = self.and(is_present, is_below);
+            let increment = self.select(is_below, _one, _zero);
+            below_count = self.add(below_count, increment);
+        }
+
+        let double_below_count = self.add(below_count, below_count);
+        self.lt(*timestamps_count, double_below_count)
+    }
+
+    /// Returns the median of the last `timestamps_count` timestamps, the one at index
+    /// `timestamps_count / 2` once sorted.
+    fn median_time_past(
+        &mut self,
+        timestamps: &[U32Variable],
+        timestamps_count: &U32Variable,
+    ) -> U32Variable {
+        let _zero = self.zero::<U32Variable>();
+        let _one = self.one::<U32Variable>();
+        let is_present = (0..timestamps.len())
+            .map(|i| {
+                let min_count = self.constant::<U32Variable>((timestamps.len() - i) as u32);
+                self.lte(min_count, *timestamps_count)
+            })
+            .collect::<Vec<_>>();
+
+        // the median is the timestamp with at most half of the timestamps strictly below it
+        // and more than half of the timestamps below or equal to it
+        let mut median = _zero;
+        for (candidate, is_candidate_present) in timestamps.iter().zip(&is_present) {
+            let mut below_count = _zero;
+            let mut not_above_count = _zero;
+            for (t, is_t_present) in timestamps.iter().zip(&is_present) {
+                let is_below = self.lt(*t, *candidate);
+                let is_below = self.and(*is_t_present, is_below);
+                let increment = self.select(is_below, _one, _zero);
+                below_count = self.add(below_count, increment);
+
+                let is_not_above = self.lte(*t, *candidate);
+                let is_not_above = self.and(*is_t_present, is_not_above);
+                let increment = self.select(is_not_above, _one, _zero);
+                not_above_count = self.add(not_above_count, increment);
+            }
+
+            let double_below_count = self.add(below_count, below_count);
+            let double_not_above_count = self.add(not_above_count, not_above_count);
+            let is_low_enough = self.lte(double_below_count, *timestamps_count);
+            let is_high_enough = self.lt(*timestamps_count, double_not_above_count);
+            let is_median = self.and(is_low_enough, is_high_enough);
+            let is_median = self.and(*is_candidate_present, is_median);
+            median = self.select(is_median, *candidate, median);
+        }
+
+        median
+    }
+
+    /// Count of the timestamps after pushing one, up to `MEDIAN_TIME_SPAN`.
+    fn push_timestamp_count(&mut self, timestamps_count: &U32Variable) -> U32Variable {
+        let _one = self.one::<U32Variable>();
+        let max_count = self.constant::<U32Variable>(MEDIAN_TIME_SPAN as u32);
+        let is_full = self.is_equal(*timestamps_count, max_count);
+        let incremented_count = self.add(*timestamps_count, _one);
+        self.select(is_full, *timestamps_count, incremented_count)
+    }
+
+    /// Returns the latest timestamp allowed for the headers, saturating at `u32::MAX`
+    /// instead of wrapping around when the reference time is close to it.
+    fn max_timestamp(
+        &mut self,
+        reference_time: &U32Variable,
+        max_future_block_time: &U32Variable,
+    ) -> U32Variable {
+        let max_timestamp = self.add(*reference_time, *max_future_block_time);
+        let is_wrapped = self.lt(max_timestamp, *reference_time);
+        let u32_max = self.constant::<U32Variable>(u32::MAX);
+        self.select(is_wrapped, u32_max, max_timestamp)
+    }
+
+    fn is_min_difficulty_allowed<P: ChainParams>(
+        &mut self,
+        parent_timestamp: &U32Variable,
+        timestamp: &U32Variable,
+    ) -> BoolVariable {
+        // more than twice the target spacing after the parent
+        let max_spacing = self.constant::<U32Variable>(2 * P::POW_TARGET_SPACING);
+        let min_difficulty_timestamp = self.add(*parent_timestamp, max_spacing);
+        self.gt(*timestamp, min_difficulty_timestamp)
     }
 
-    fn adjust_threshold(
+    fn is_valid_version(
+        &mut self,
+        version: &U32Variable,
+        height: &U64Variable,
+        activations: &VersionActivations,
+    ) -> BoolVariable {
+        let _false = self._false();
+
+        // minimum versions increase with each soft fork, so the last active one applies
+        let mut min_version = self.zero::<U32Variable>();
+        let mut is_enforced = _false;
+        for (activation_height, activation_version) in activations.min_versions() {
+            let activation_height = self.constant::<U64Variable>(activation_height);
+            let is_active = self.lte(activation_height, *height);
+            let activation_version = self.constant::<U32Variable>(activation_version);
+            min_version = self.select(is_active, activation_version, min_version);
+            is_enforced = self.or(is_enforced, is_active);
+        }
+
+        // versions are signed, so a set top bit is below any minimum version
+        let is_negative = self.to_be_bits(*version)[0];
+        let is_non_negative = self.not(is_negative);
+        let is_above_min_version = self.lte(min_version, *version);
+        let is_valid = self.and(is_non_negative, is_above_min_version);
+
+        let is_not_enforced = self.not(is_enforced);
+        self.or(is_not_enforced, is_valid)
+    }
+
+    fn adjust_threshold<P: ChainParams>(
         &mut self,
         threshold: &ThresholdVariable,
         period_start_timestamp: U32Variable,
         period_end_timestamp: U32Variable,
     ) -> U256Variable {
-        let pow_ts_min = self.constant::<U32Variable>(2016 * 600 / 4);
-        let pow_ts_max = self.constant::<U32Variable>(2016 * 600 * 4);
+        // the target is kept as is on networks without retargeting
+        if P::POW_NO_RETARGETING {
+            return *threshold;
+        }
 
-        let pow_ts = u256_from_gen(|i| {
-            self.constant::<U32Variable>(if i == 0 { 2016 * 600 } else { 0 })
-                .variable
-        });
+        let pow_target_timespan = P::pow_target_timespan();
+        let pow_ts_min = self.constant::<U32Variable>(pow_target_timespan / 4);
+        let pow_ts_max = self.constant::<U32Variable>(pow_target_timespan * 4);
 
-        let pow_limit = u256_from_gen(|i| {
-            self.constant::<U32Variable>(if i == 7 { 0 } else { u32::MAX })
-                .variable
-        });
+        let pow_ts = self.constant::<U256Variable>(U256::from(pow_target_timespan));
+
+        let pow_limit =
+            self.constant::<U256Variable>(U256::from_big_endian(&P::pow_limit().to_bytes_be()));
 
         let timespan = self.sub(period_end_timestamp, period_start_timestamp);
 
@@ -196,10 +543,24 @@ impl<L: PlonkParameters<D>, const D: usize> BitcoinMultiVerify<L, D> for Circuit
         let dividend = self.mul(*threshold, timespan_adjusted);
 
         let new_target = self.div(dividend, pow_ts);
-        let is_lower_pow_limit = self.is_zero(new_target.limbs[7].variable);
+        let is_lower_pow_limit = self.lte(new_target, pow_limit);
 
         self.select(is_lower_pow_limit, new_target, pow_limit)
     }
+
+    fn compute_work(&mut self, threshold: &ThresholdVariable) -> WorkVariable {
+        // 2^256 / (threshold + 1) computed as !threshold / (threshold + 1) + 1
+        let not_threshold = u256_from_gen(|i| {
+            let limb_max = self.constant::<U32Variable>(u32::MAX);
+            self.sub(limb_max, threshold.limbs[i]).variable
+        });
+
+        let one = self.one::<WorkVariable>();
+        let denominator = self.add(*threshold, one);
+        let quotient = self.div(not_threshold, denominator);
+
+        self.add(quotient, one)
+    }
 }
 
 #[cfg(test)]
@@ -212,6 +573,8 @@ mod test {
     use plonky2x::prelude::DefaultBuilder;
 
     use super::*;
+    use crate::consts::MAX_FUTURE_BLOCK_TIME;
+    use crate::params::{Mainnet, Testnet3, MAINNET_VERSION_ACTIVATIONS};
     use crate::utils::*;
 
     fn test_adjust_threshold_template(
@@ -229,8 +592,11 @@ mod test {
         let period_start_timestamp = builder.read::<U32Variable>();
         let period_end_timestamp = builder.read::<U32Variable>();
 
-        let adjusted_threshold =
-            builder.adjust_threshold(&threshold, period_start_timestamp, period_end_timestamp);
+        let adjusted_threshold = builder.adjust_threshold::<Mainnet>(
+            &threshold,
+            period_start_timestamp,
+            period_end_timestamp,
+        );
         builder.write(adjusted_threshold);
 
         log::debug!("Building circuit");
@@ -256,7 +622,7 @@ mod test {
         let adjusted_threshold = _output.read::<ThresholdVariable>();
 
         let expected_threshold = U256::from_little_endian(
-            adjust_threshold(
+            adjust_threshold::<Mainnet>(
                 BigUint::from_str(period_threshold).unwrap(),
                 period_start_ts,
                 period_end_ts,
@@ -290,4 +656,187 @@ mod test {
             2419200, // 14 * 24 * 60 * 60 * 2
         );
     }
+
+    #[test]
+    fn test_adjust_threshold_min_timespan() {
+        test_adjust_threshold_template(
+            "8825801199382903987726989797449454220615414953524072026210304",
+            1349226660,
+            1349226660 + 100000, // below 14 * 24 * 60 * 60 / 4
+        );
+    }
+
+    #[test]
+    fn test_adjust_threshold_max_timespan() {
+        test_adjust_threshold_template(
+            "8825801199382903987726989797449454220615414953524072026210304",
+            1349226660,
+            1349226660 + 5000000, // above 14 * 24 * 60 * 60 * 4
+        );
+    }
+
+    #[test]
+    fn test_compute_work() {
+        env::set_var("RUST_LOG", "debug");
+        env_logger::try_init().unwrap_or_default();
+
+        let mut builder = DefaultBuilder::new();
+        let threshold = builder.read::<ThresholdVariable>();
+        let work = builder.compute_work(&threshold);
+        builder.write(work);
+        let circuit = builder.build();
+
+        let period_threshold = "8825801199382903987726989797449454220615414953524072026210304";
+        let mut input = circuit.input();
+        input.write::<ThresholdVariable>(U256::from_dec_str(period_threshold).unwrap());
+
+        let (proof, mut output) = circuit.prove(&input);
+        circuit.verify(&proof, &input, &output);
+
+        let expected_work = U256::from_little_endian(
+            compute_work(BigUint::from_str(period_threshold).unwrap())
+                .to_bytes_le()
+                .as_slice(),
+        );
+        assert_eq!(output.read::<WorkVariable>(), expected_work);
+    }
+
+    #[test]
+    fn test_median_time_past() {
+        env::set_var("RUST_LOG", "debug");
+        env_logger::try_init().unwrap_or_default();
+
+        let mut builder = DefaultBuilder::new();
+        let timestamps = builder.read::<ArrayVariable<U32Variable, MEDIAN_TIME_SPAN>>();
+        let timestamps_count = builder.read::<U32Variable>();
+        let timestamp = builder.read::<U32Variable>();
+        let median = builder.median_time_past(&timestamps.as_vec(), &timestamps_count);
+        let is_after =
+            builder.is_after_median_time_past(&timestamps.as_vec(), &timestamps_count, &timestamp);
+        builder.write(median);
+        builder.write(is_after);
+        let circuit = builder.build();
+
+        // unordered with duplicates around the median, as allowed between blocks, and a
+        // chain of 4 headers whose padding before genesis is left out
+        for (timestamps_values, timestamps_count) in [
+            (
+                vec![1000, 1600, 1300, 1300, 900, 2000, 1300, 1100, 1700, 0, 1250],
+                11,
+            ),
+            (vec![0, 0, 0, 0, 0, 0, 0, 1000, 1600, 1300, 1200], 4),
+        ] {
+            let mut sorted =
+                timestamps_values[MEDIAN_TIME_SPAN - timestamps_count as usize..].to_vec();
+            sorted.sort();
+            let expected_median = sorted[timestamps_count as usize / 2];
+            assert_eq!(expected_median, 1300);
+
+            for (timestamp_value, expected_is_after) in [(1301, true), (1300, false), (1299, false)]
+            {
+                let mut input = circuit.input();
+                input.write::<ArrayVariable<U32Variable, MEDIAN_TIME_SPAN>>(
+                    timestamps_values.clone(),
+                );
+                input.write::<U32Variable>(timestamps_count);
+                input.write::<U32Variable>(timestamp_value);
+
+                let (proof, mut output) = circuit.prove(&input);
+                circuit.verify(&proof, &input, &output);
+
+                assert_eq!(output.read::<U32Variable>(), expected_median);
+                assert_eq!(output.read::<BoolVariable>(), expected_is_after);
+            }
+        }
+    }
+
+    #[test]
+    fn test_is_valid_version() {
+        env::set_var("RUST_LOG", "debug");
+        env_logger::try_init().unwrap_or_default();
+
+        let mut builder = DefaultBuilder::new();
+        let version = builder.read::<U32Variable>();
+        let height = builder.read::<U64Variable>();
+        let is_valid = builder.is_valid_version(&version, &height, &MAINNET_VERSION_ACTIVATIONS);
+        builder.write(is_valid);
+        let circuit = builder.build();
+
+        for (version_value, height_value) in [
+            (1, 227930),
+            (1, 227931),
+            (2, 363724),
+            (2, 363725),
+            (3, 388381),
+            (4, 388381),
+            (0x20000000, 840000),
+            (0xe0000000, 840000),
+        ] {
+            let mut input = circuit.input();
+            input.write::<U32Variable>(version_value);
+            input.write::<U64Variable>(height_value);
+
+            let (proof, mut output) = circuit.prove(&input);
+            circuit.verify(&proof, &input, &output);
+
+            assert_eq!(
+                output.read::<BoolVariable>(),
+                MAINNET_VERSION_ACTIVATIONS.is_valid_version(height_value, version_value as i32)
+            );
+        }
+    }
+
+    #[test]
+    fn test_max_timestamp() {
+        env::set_var("RUST_LOG", "debug");
+        env_logger::try_init().unwrap_or_default();
+
+        let mut builder = DefaultBuilder::new();
+        let reference_time = builder.read::<U32Variable>();
+        let max_future_block_time = builder.read::<U32Variable>();
+        let max_timestamp = builder.max_timestamp(&reference_time, &max_future_block_time);
+        builder.write(max_timestamp);
+        let circuit = builder.build();
+
+        for (reference_time_value, expected_max_timestamp) in [
+            (1700000000, 1700000000 + MAX_FUTURE_BLOCK_TIME),
+            (u32::MAX - MAX_FUTURE_BLOCK_TIME, u32::MAX),
+            (u32::MAX - 1, u32::MAX),
+        ] {
+            let mut input = circuit.input();
+            input.write::<U32Variable>(reference_time_value);
+            input.write::<U32Variable>(MAX_FUTURE_BLOCK_TIME);
+
+            let (proof, mut output) = circuit.prove(&input);
+            circuit.verify(&proof, &input, &output);
+
+            assert_eq!(output.read::<U32Variable>(), expected_max_timestamp);
+        }
+    }
+
+    #[test]
+    fn test_is_min_difficulty_allowed() {
+        env::set_var("RUST_LOG", "debug");
+        env_logger::try_init().unwrap_or_default();
+
+        let mut builder = DefaultBuilder::new();
+        let parent_timestamp = builder.read::<U32Variable>();
+        let timestamp = builder.read::<U32Variable>();
+        let is_allowed =
+            builder.is_min_difficulty_allowed::<Testnet3>(&parent_timestamp, &timestamp);
+        builder.write(is_allowed);
+        let circuit = builder.build();
+
+        // testnet3 allows minimum difficulty blocks after more than 20 minutes
+        for (spacing, expected) in [(600, false), (1200, false), (1201, true)] {
+            let mut input = circuit.input();
+            input.write::<U32Variable>(1700000000);
+            input.write::<U32Variable>(1700000000 + spacing);
+
+            let (proof, mut output) = circuit.prove(&input);
+            circuit.verify(&proof, &input, &output);
+
+            assert_eq!(output.read::<BoolVariable>(), expected);
+        }
+    }
 }
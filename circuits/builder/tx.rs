@@ -0,0 +1,850 @@
+use plonky2x::frontend::vars::EvmVariable;
+use plonky2x::prelude::{
+    BoolVariable, ByteVariable, Bytes32Variable, BytesVariable, CircuitBuilder, CircuitVariable,
+    LessThanOrEqual, PlonkParameters, U32Variable, U64Variable,
+};
+
+use crate::consts::*;
+use crate::vars::*;
+
+pub trait BitcoinTxVerify<L: PlonkParameters<D>, const D: usize> {
+    fn calculate_txid<const MAX_TX_BYTES: usize>(
+        &mut self,
+        tx_bytes: &BytesVariable<MAX_TX_BYTES>,
+        tx_length: &U32Variable,
+    ) -> TxidVariable;
+
+    fn calculate_merkle_root(
+        &mut self,
+        leaf: &Bytes32Variable,
+        index: &U32Variable,
+        depth: &U32Variable,
+        branch: &MerkleBranchVariable,
+    ) -> Bytes32Variable;
+
+    fn verify_tx_inclusion<const MAX_TX_BYTES: usize>(
+        &mut self,
+        header: &BitcoinHeaderVariable,
+        tx_bytes: &BytesVariable<MAX_TX_BYTES>,
+        tx_length: &U32Variable,
+        index: &U32Variable,
+        depth: &U32Variable,
+        branch: &MerkleBranchVariable,
+    ) -> TxidVariable;
+
+    fn get_byte_at<const N: usize>(
+        &mut self,
+        bytes: &BytesVariable<N>,
+        offset: &U32Variable,
+    ) -> ByteVariable;
+
+    fn read_varint<const N: usize>(
+        &mut self,
+        bytes: &BytesVariable<N>,
+        offset: &U32Variable,
+    ) -> (U32Variable, U32Variable);
+
+    fn has_witness_marker<const MAX_TX_BYTES: usize>(
+        &mut self,
+        tx_bytes: &BytesVariable<MAX_TX_BYTES>,
+    ) -> BoolVariable;
+
+    fn get_tx_outputs<const MAX_TX_BYTES: usize>(
+        &mut self,
+        tx_bytes: &BytesVariable<MAX_TX_BYTES>,
+    ) -> (U32Variable, U32Variable);
+
+    fn get_tx_output<const MAX_TX_BYTES: usize>(
+        &mut self,
+        tx_bytes: &BytesVariable<MAX_TX_BYTES>,
+        vout: &U32Variable,
+    ) -> TxOutputVariable;
+
+    fn calculate_script_hash(&mut self, output: &TxOutputVariable) -> Bytes32Variable;
+
+    fn get_op_return_payload(
+        &mut self,
+        output: &TxOutputVariable,
+    ) -> (U32Variable, OpReturnPayloadVariable);
+
+    fn get_coinbase_height<const MAX_TX_BYTES: usize>(
+        &mut self,
+        tx_bytes: &BytesVariable<MAX_TX_BYTES>,
+    ) -> U64Variable;
+
+    fn verify_coinbase_height<const MAX_TX_BYTES: usize>(
+        &mut self,
+        header: &BitcoinHeaderVariable,
+        tx_bytes: &BytesVariable<MAX_TX_BYTES>,
+        tx_length: &U32Variable,
+        depth: &U32Variable,
+        branch: &MerkleBranchVariable,
+    ) -> U64Variable;
+
+    fn get_witness_commitment<const MAX_TX_BYTES: usize>(
+        &mut self,
+        tx_bytes: &BytesVariable<MAX_TX_BYTES>,
+    ) -> Bytes32Variable;
+
+    fn verify_witness_commitment(
+        &mut self,
+        commitment: &Bytes32Variable,
+        witness_root: &Bytes32Variable,
+        witness_reserved_value: &Bytes32Variable,
+    );
+}
+
+impl<L: PlonkParameters<D>, const D: usize> BitcoinTxVerify<L, D> for CircuitBuilder<L, D> {
+    fn calculate_txid<const MAX_TX_BYTES: usize>(
+        &mut self,
+        tx_bytes: &BytesVariable<MAX_TX_BYTES>,
+        tx_length: &U32Variable,
+    ) -> TxidVariable {
+        let _true = self._true();
+        let _false = self._false();
+
+        // the transaction and its sha256 padding have to fit in the buffer
+        let max_length =
+            self.constant::<U32Variable>((MAX_TX_BYTES - SHA256_PADDING_MIN_BYTES) as u32);
+        let is_in_range = tx_length.lte(max_length, self);
+        self.assert_is_equal(is_in_range, _true);
+
+        // a 64-byte transaction could be passed off as an inner node of the merkle tree
+        let inner_node_length = self.constant::<U32Variable>(64);
+        let is_inner_node_length = self.is_equal(*tx_length, inner_node_length);
+        self.assert_is_equal(is_inner_node_length, _false);
+
+        let sha256_1 = self.curta_sha256_variable(&tx_bytes.0, *tx_length);
+        self.curta_sha256(&sha256_1.as_bytes())
+    }
+
+    fn calculate_merkle_root(
+        &mut self,
+        leaf: &Bytes32Variable,
+        index: &U32Variable,
+        depth: &U32Variable,
+        branch: &MerkleBranchVariable,
+    ) -> Bytes32Variable {
+        let _true = self._true();
+        let _false = self._false();
+
+        let max_depth = self.constant::<U32Variable>(TX_MERKLE_MAX_DEPTH as u32);
+        let is_valid_depth = depth.lte(max_depth, self);
+        self.assert_is_equal(is_valid_depth, _true);
+
+        // bit i of the index gives the side of the node at level i
+        let index_bits = self.to_be_bits(*index);
+
+        let mut node = *leaf;
+        for (i, index_bit) in index_bits.iter().rev().enumerate() {
+            let level = self.constant::<U32Variable>(i as u32);
+            let is_above_root = depth.lte(level, self);
+            let is_active = self.not(is_above_root);
+
+            // the index has to address a leaf of a tree of the given depth
+            let is_out_of_range = self.and(is_above_root, *index_bit);
+            self.assert_is_equal(is_out_of_range, _false);
+
+            if i < TX_MERKLE_MAX_DEPTH {
+                let left = self.select(*index_bit, branch[i], node);
+                let right = self.select(*index_bit, node, branch[i]);
+                let parent_bytes = [left.as_bytes(), right.as_bytes()].concat();
+                let sha256_1 = self.curta_sha256(&parent_bytes);
+                let parent = self.curta_sha256(&sha256_1.as_bytes());
+                node = self.select(is_active, parent, node);
+            }
+        }
+
+        node
+    }
+
+    fn verify_tx_inclusion<const MAX_TX_BYTES: usize>(
+        &mut self,
+        header: &BitcoinHeaderVariable,
+        tx_bytes: &BytesVariable<MAX_TX_BYTES>,
+        tx_length: &U32Variable,
+        index: &U32Variable,
+        depth: &U32Variable,
+        branch: &MerkleBranchVariable,
+    ) -> TxidVariable {
+        let txid = self.calculate_txid(tx_bytes, tx_length);
+        let merkle_root = self.calculate_merkle_root(&txid, index, depth, branch);
+        self.assert_is_equal(merkle_root, header.merkle_root);
+        txid
+    }
+
+    fn get_byte_at<const N: usize>(
+        &mut self,
+        bytes: &BytesVariable<N>,
+        offset: &U32Variable,
+    ) -> ByteVariable {
+        self.select_array(&bytes.0, offset.variables()[0])
+    }
+
+    /// Returns the value and the size in bytes of the CompactSize integer at `offset`. Only
+    /// the low 4 bytes of an 8-byte integer are read: such a count or length cannot occur in
+    /// a transaction that fits the buffer, and offsets past the parsed data are garbage.
+    fn read_varint<const N: usize>(
+        &mut self,
+        bytes: &BytesVariable<N>,
+        offset: &U32Variable,
+    ) -> (U32Variable, U32Variable) {
+        let _zero = self.constant::<ByteVariable>(0);
+
+        let varint_bytes = (0..5)
+            .map(|i| {
+                let i = self.constant::<U32Variable>(i);
+                let byte_offset = self.add(*offset, i);
+                self.get_byte_at(bytes, &byte_offset)
+            })
+            .collect::<Vec<_>>();
+
+        let prefix_u16 = self.constant::<ByteVariable>(0xfd);
+        let prefix_u32 = self.constant::<ByteVariable>(0xfe);
+        let prefix_u64 = self.constant::<ByteVariable>(0xff);
+        let is_u16 = self.is_equal(varint_bytes[0], prefix_u16);
+        let is_u32 = self.is_equal(varint_bytes[0], prefix_u32);
+        let is_u64 = self.is_equal(varint_bytes[0], prefix_u64);
+        let is_u32_or_u64 = self.or(is_u32, is_u64);
+
+        // little-endian bytes, decoded as big-endian u32
+        let value_u8 = U32Variable::decode(self, &[_zero, _zero, _zero, varint_bytes[0]]);
+        let value_u16 =
+            U32Variable::decode(self, &[_zero, _zero, varint_bytes[2], varint_bytes[1]]);
+        let value_u32 = U32Variable::decode(
+            self,
+            &[
+                varint_bytes[4],
+                varint_bytes[3],
+                varint_bytes[2],
+                varint_bytes[1],
+            ],
+        );
+
+        let size_u8 = self.constant::<U32Variable>(1);
+        let size_u16 = self.constant::<U32Variable>(3);
+        let size_u32 = self.constant::<U32Variable>(5);
+        let size_u64 = self.constant::<U32Variable>(9);
+
+        let value = self.select(is_u16, value_u16, value_u8);
+        let value = self.select(is_u32_or_u64, value_u32, value);
+        let size = self.select(is_u16, size_u16, size_u8);
+        let size = self.select(is_u32, size_u32, size);
+        let size = self.select(is_u64, size_u64, size);
+
+        (value, size)
+    }
+
+    /// The segwit serialization has a zero marker byte where the inputs count would be.
+    fn has_witness_marker<const MAX_TX_BYTES: usize>(
+        &mut self,
+        tx_bytes: &BytesVariable<MAX_TX_BYTES>,
+    ) -> BoolVariable {
+        let _zero = self.constant::<ByteVariable>(0);
+        self.is_equal(tx_bytes[TX_WITNESS_MARKER_INDEX], _zero)
+    }
+
+    /// Returns the number of outputs and the offset of the first one.
+    fn get_tx_outputs<const MAX_TX_BYTES: usize>(
+        &mut self,
+        tx_bytes: &BytesVariable<MAX_TX_BYTES>,
+    ) -> (U32Variable, U32Variable) {
+        // constants
+        let _true = self._true();
+        let outpoint_length = self.constant::<U32Variable>(TX_OUTPOINT_BYTES_LENGTH as u32);
+        let sequence_length = self.constant::<U32Variable>(TX_SEQUENCE_BYTES_LENGTH as u32);
+
+        // skip the version, and the marker and flag bytes if present
+        let has_witness = self.has_witness_marker(tx_bytes);
+        let legacy_inputs_offset = self.constant::<U32Variable>(TX_WITNESS_MARKER_INDEX as u32);
+        let witness_inputs_offset =
+            self.constant::<U32Variable>(TX_WITNESS_MARKER_INDEX as u32 + 2);
+        let mut offset = self.select(has_witness, witness_inputs_offset, legacy_inputs_offset);
+
+        // skip the inputs
+        let (inputs_count, size) = self.read_varint(tx_bytes, &offset);
+        let max_inputs = self.constant::<U32Variable>(TX_MAX_INPUTS as u32);
+        let is_valid_inputs_count = inputs_count.lte(max_inputs, self);
+        self.assert_is_equal(is_valid_inputs_count, _true);
+        offset = self.add(offset, size);
+
+        for i in 0..TX_MAX_INPUTS {
+            let i = self.constant::<U32Variable>(i as u32);
+            let is_past_inputs = inputs_count.lte(i, self);
+
+            let script_length_offset = self.add(offset, outpoint_length);
+            let (script_length, size) = self.read_varint(tx_bytes, &script_length_offset);
+            let script_offset = self.add(script_length_offset, size);
+            let script_end = self.add(script_offset, script_length);
+            let input_end = self.add(script_end, sequence_length);
+            offset = self.select(is_past_inputs, offset, input_end);
+        }
+
+        let (outputs_count, size) = self.read_varint(tx_bytes, &offset);
+        let max_outputs = self.constant::<U32Variable>(TX_MAX_OUTPUTS as u32);
+        let is_valid_outputs_count = outputs_count.lte(max_outputs, self);
+        self.assert_is_equal(is_valid_outputs_count, _true);
+        offset = self.add(offset, size);
+
+        (outputs_count, offset)
+    }
+
+    fn get_tx_output<const MAX_TX_BYTES: usize>(
+        &mut self,
+        tx_bytes: &BytesVariable<MAX_TX_BYTES>,
+        vout: &U32Variable,
+    ) -> TxOutputVariable {
+        // constants
+        let _true = self._true();
+        let _zero = self.constant::<ByteVariable>(0);
+        let value_length = self.constant::<U32Variable>(TX_VALUE_BYTES_LENGTH as u32);
+
+        // find the requested output, the outputs before it are all present
+        let (outputs_count, mut offset) = self.get_tx_outputs(tx_bytes);
+        let is_missing_vout = outputs_count.lte(*vout, self);
+        let is_valid_vout = self.not(is_missing_vout);
+        self.assert_is_equal(is_valid_vout, _true);
+
+        let mut output_offset = offset;
+        for i in 0..TX_MAX_OUTPUTS {
+            let i = self.constant::<U32Variable>(i as u32);
+            let is_vout = self.is_equal(i, *vout);
+            output_offset = self.select(is_vout, offset, output_offset);
+
+            let script_length_offset = self.add(offset, value_length);
+            let (script_length, size) = self.read_varint(tx_bytes, &script_length_offset);
+            let script_offset = self.add(script_length_offset, size);
+            offset = self.add(script_offset, script_length);
+        }
+
+        // value, little-endian
+        let value_bytes = (0..TX_VALUE_BYTES_LENGTH)
+            .rev()
+            .map(|i| {
+                let i = self.constant::<U32Variable>(i as u32);
+                let byte_offset = self.add(output_offset, i);
+                self.get_byte_at(tx_bytes, &byte_offset)
+            })
+            .collect::<Vec<_>>();
+        let value = U64Variable::decode(self, &value_bytes);
+
+        // scriptPubKey, zero-padded so that the output is canonical
+        let script_length_offset = self.add(output_offset, value_length);
+        let (script_length, size) = self.read_varint(tx_bytes, &script_length_offset);
+        let max_script_length = self
+            .constant::<U32Variable>((SCRIPT_PUBKEY_MAX_BYTES - SHA256_PADDING_MIN_BYTES) as u32);
+        let is_valid_script_length = script_length.lte(max_script_length, self);
+        self.assert_is_equal(is_valid_script_length, _true);
+        let script_offset = self.add(script_length_offset, size);
+
+        let script = (0..SCRIPT_PUBKEY_MAX_BYTES)
+            .map(|i| {
+                let i = self.constant::<U32Variable>(i as u32);
+                let byte_offset = self.add(script_offset, i);
+                let byte = self.get_byte_at(tx_bytes, &byte_offset);
+                let is_past_script = script_length.lte(i, self);
+                self.select(is_past_script, _zero, byte)
+            })
+            .collect::<Vec<_>>();
+
+        TxOutputVariable {
+            value,
+            script_length,
+            script: BytesVariable(script.try_into().unwrap()),
+        }
+    }
+
+    fn calculate_script_hash(&mut self, output: &TxOutputVariable) -> Bytes32Variable {
+        self.curta_sha256_variable(&output.script.0, output.script_length)
+    }
+
+    /// Returns the length and the zero-padded payload of an `OP_RETURN <push>` script.
+    fn get_op_return_payload(
+        &mut self,
+        output: &TxOutputVariable,
+    ) -> (U32Variable, OpReturnPayloadVariable) {
+        // constants
+        let _true = self._true();
+        let _zero = self.constant::<ByteVariable>(0);
+        let _zero_u32 = self.zero::<U32Variable>();
+        let _one_u32 = self.one::<U32Variable>();
+        let op_return = self.constant::<ByteVariable>(OP_RETURN);
+        let op_pushdata1 = self.constant::<ByteVariable>(OP_PUSHDATA1);
+        let op_pushbytes_max = self.constant::<U32Variable>(OP_PUSHBYTES_MAX as u32);
+        let max_payload_length = self.constant::<U32Variable>(OP_RETURN_MAX_PAYLOAD_BYTES as u32);
+
+        let script = &output.script;
+        let is_op_return = self.is_equal(script[0], op_return);
+        self.assert_is_equal(is_op_return, _true);
+
+        // the script is OP_RETURN alone, or followed by a single direct or OP_PUSHDATA1 push
+        let is_empty = self.is_equal(output.script_length, _one_u32);
+        let opcode = U32Variable::decode(self, &[_zero, _zero, _zero, script[1]]);
+        let is_pushbytes = opcode.lte(op_pushbytes_max, self);
+        let is_pushdata1 = self.is_equal(script[1], op_pushdata1);
+        let is_push = self.or(is_pushbytes, is_pushdata1);
+        let is_valid_script = self.or(is_empty, is_push);
+        self.assert_is_equal(is_valid_script, _true);
+
+        let pushdata1_length = U32Variable::decode(self, &[_zero, _zero, _zero, script[2]]);
+        let length = self.select(is_pushdata1, pushdata1_length, opcode);
+        let length = self.select(is_empty, _zero_u32, length);
+        let is_valid_length = length.lte(max_payload_length, self);
+        self.assert_is_equal(is_valid_length, _true);
+
+        let pushbytes_offset = self.constant::<U32Variable>(2);
+        let pushdata1_offset = self.constant::<U32Variable>(3);
+        let offset = self.select(is_pushdata1, pushdata1_offset, pushbytes_offset);
+        let offset = self.select(is_empty, _one_u32, offset);
+        let script_length = self.add(offset, length);
+        self.assert_is_equal(script_length, output.script_length);
+
+        // the script is zero-padded, so the bytes past the push are zero
+        let payload = (0..OP_RETURN_MAX_PAYLOAD_BYTES)
+            .map(|i| self.select(is_pushdata1, script[i + 3], script[i + 2]))
+            .collect::<Vec<_>>();
+
+        (length, BytesVariable(payload.try_into().unwrap()))
+    }
+
+    /// Returns the BIP34 height pushed at the start of the coinbase script. The transaction
+    /// has to be serialized without witness data.
+    fn get_coinbase_height<const MAX_TX_BYTES: usize>(
+        &mut self,
+        tx_bytes: &BytesVariable<MAX_TX_BYTES>,
+    ) -> U64Variable {
+        // constants
+        let _true = self._true();
+        let _zero = self.constant::<ByteVariable>(0);
+        let _one = self.constant::<ByteVariable>(1);
+        let _max = self.constant::<ByteVariable>(0xff);
+
+        // a single input spending the null outpoint
+        self.assert_is_equal(tx_bytes[TX_WITNESS_MARKER_INDEX], _one);
+        for i in 0..32 {
+            self.assert_is_equal(tx_bytes[COINBASE_PREVOUT_INDEX + i], _zero);
+        }
+        for i in 32..TX_OUTPOINT_BYTES_LENGTH {
+            self.assert_is_equal(tx_bytes[COINBASE_PREVOUT_INDEX + i], _max);
+        }
+
+        // the script starts with OP_1..OP_16 or a push of up to 4 little-endian bytes
+        let opcode = tx_bytes[COINBASE_SCRIPT_INDEX];
+        let opcode_u32 = U32Variable::decode(self, &[_zero, _zero, _zero, opcode]);
+        let op_1 = self.constant::<U32Variable>(OP_1 as u32);
+        let op_16 = self.constant::<U32Variable>(OP_16 as u32);
+        let min_push = self.constant::<U32Variable>(1);
+        let max_push = self.constant::<U32Variable>(4);
+
+        let is_above_op_1 = op_1.lte(opcode_u32, self);
+        let is_below_op_16 = opcode_u32.lte(op_16, self);
+        let is_small_int = self.and(is_above_op_1, is_below_op_16);
+        let is_above_min_push = min_push.lte(opcode_u32, self);
+        let is_below_max_push = opcode_u32.lte(max_push, self);
+        let is_push = self.and(is_above_min_push, is_below_max_push);
+        let is_valid_opcode = self.or(is_small_int, is_push);
+        self.assert_is_equal(is_valid_opcode, _true);
+
+        let small_int_offset = self.constant::<U32Variable>(OP_1 as u32 - 1);
+        let small_int = self.sub(opcode_u32, small_int_offset);
+        let small_int_bytes = small_int.encode(self);
+
+        let pushed_bytes = (1..=4)
+            .map(|i| {
+                let i_u32 = self.constant::<U32Variable>(i as u32);
+                let is_pushed = i_u32.lte(opcode_u32, self);
+                let byte = self.select(is_pushed, tx_bytes[COINBASE_SCRIPT_INDEX + i], _zero);
+                self.select(is_small_int, small_int_bytes[4 - i], byte)
+            })
+            .collect::<Vec<_>>();
+
+        // little-endian bytes, decoded as big-endian u64
+        U64Variable::decode(
+            self,
+            &[
+                _zero,
+                _zero,
+                _zero,
+                _zero,
+                pushed_bytes[3],
+                pushed_bytes[2],
+                pushed_bytes[1],
+                pushed_bytes[0],
+            ],
+        )
+    }
+
+    /// Verifies that the first transaction of the block is the given coinbase and returns
+    /// its BIP34 height.
+    fn verify_coinbase_height<const MAX_TX_BYTES: usize>(
+        &mut self,
+        header: &BitcoinHeaderVariable,
+        tx_bytes: &BytesVariable<MAX_TX_BYTES>,
+        tx_length: &U32Variable,
+        depth: &U32Variable,
+        branch: &MerkleBranchVariable,
+    ) -> U64Variable {
+        let index = self.zero::<U32Variable>();
+        self.verify_tx_inclusion(header, tx_bytes, tx_length, &index, depth, branch);
+        self.get_coinbase_height(tx_bytes)
+    }
+
+    /// Returns the BIP141 witness commitment of a coinbase: the last output whose script
+    /// starts with `OP_RETURN 0xaa21a9ed` followed by 32 bytes.
+    fn get_witness_commitment<const MAX_TX_BYTES: usize>(
+        &mut self,
+        tx_bytes: &BytesVariable<MAX_TX_BYTES>,
+    ) -> Bytes32Variable {
+        // constants
+        let _true = self._true();
+        let _false = self._false();
+        let value_length = self.constant::<U32Variable>(TX_VALUE_BYTES_LENGTH as u32);
+        let header_length = self.constant::<U32Variable>(WITNESS_COMMITMENT_HEADER.len() as u32);
+        let min_script_length =
+            self.constant::<U32Variable>(WITNESS_COMMITMENT_SCRIPT_MIN_BYTES as u32);
+        let commitment_header = WITNESS_COMMITMENT_HEADER
+            .iter()
+            .map(|byte| self.constant::<ByteVariable>(*byte))
+            .collect::<Vec<_>>();
+
+        let (outputs_count, mut offset) = self.get_tx_outputs(tx_bytes);
+
+        let mut has_commitment = _false;
+        let mut commitment_offset = offset;
+        for i in 0..TX_MAX_OUTPUTS {
+            let i = self.constant::<U32Variable>(i as u32);
+            let is_past_outputs = outputs_count.lte(i, self);
+            let is_output = self.not(is_past_outputs);
+
+            let script_length_offset = self.add(offset, value_length);
+            let (script_length, size) = self.read_varint(tx_bytes, &script_length_offset);
+            let script_offset = self.add(script_length_offset, size);
+
+            let is_long_enough = min_script_length.lte(script_length, self);
+            let mut is_commitment = self.and(is_output, is_long_enough);
+            for (j, header_byte) in commitment_header.iter().enumerate() {
+                let j = self.constant::<U32Variable>(j as u32);
+                let byte_offset = self.add(script_offset, j);
+                let byte = self.get_byte_at(tx_bytes, &byte_offset);
+                let is_header_byte = self.is_equal(byte, *header_byte);
+                is_commitment = self.and(is_commitment, is_header_byte);
+            }
+
+            let output_commitment_offset = self.add(script_offset, header_length);
+            commitment_offset =
+                self.select(is_commitment, output_commitment_offset, commitment_offset);
+            has_commitment = self.or(has_commitment, is_commitment);
+
+            offset = self.add(script_offset, script_length);
+        }
+        self.assert_is_equal(has_commitment, _true);
+
+        let commitment = (0..32)
+            .map(|i| {
+                let i = self.constant::<U32Variable>(i);
+                let byte_offset = self.add(commitment_offset, i);
+                self.get_byte_at(tx_bytes, &byte_offset)
+            })
+            .collect::<Vec<_>>();
+        commitment.as_slice().try_into().unwrap()
+    }
+
+    /// The commitment is the double SHA256 of the root of the wtxid tree followed by the
+    /// reserved value from the coinbase witness.
+    fn verify_witness_commitment(
+        &mut self,
+        commitment: &Bytes32Variable,
+        witness_root: &Bytes32Variable,
+        witness_reserved_value: &Bytes32Variable,
+    ) {
+        let bytes = [witness_root.as_bytes(), witness_reserved_value.as_bytes()].concat();
+        let sha256_1 = self.curta_sha256(&bytes);
+        let expected_commitment = self.curta_sha256(&sha256_1.as_bytes());
+        self.assert_is_equal(expected_commitment, *commitment);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+
+    use bitcoincore_rpc::bitcoin::blockdata::constants::genesis_block;
+    use bitcoincore_rpc::bitcoin::consensus::serialize;
+    use bitcoincore_rpc::bitcoin::hashes::{sha256, sha256d, Hash};
+    use bitcoincore_rpc::bitcoin::script::Builder;
+    use bitcoincore_rpc::bitcoin::script::PushBytesBuf;
+    use bitcoincore_rpc::bitcoin::{Amount, Network, ScriptBuf, TxOut};
+    use ethers::types::H256;
+    use plonky2x::prelude::DefaultBuilder;
+
+    use super::*;
+
+    #[test]
+    fn test_calculate_merkle_root() {
+        env::set_var("RUST_LOG", "debug");
+        env_logger::try_init().unwrap_or_default();
+
+        const MAX_TX_BYTES: usize = 256;
+
+        log::debug!("Defining circuit");
+        let mut builder = DefaultBuilder::new();
+
+        let tx_bytes = builder.read::<BytesVariable<MAX_TX_BYTES>>();
+        let tx_length = builder.read::<U32Variable>();
+        let index = builder.read::<U32Variable>();
+        let depth = builder.read::<U32Variable>();
+        let branch = builder.read::<MerkleBranchVariable>();
+
+        let txid = builder.calculate_txid(&tx_bytes, &tx_length);
+        let merkle_root = builder.calculate_merkle_root(&txid, &index, &depth, &branch);
+        builder.write(txid);
+        builder.write(merkle_root);
+
+        log::debug!("Building circuit");
+        let circuit = builder.build();
+        log::debug!("Done building circuit");
+
+        let tx = serialize(&genesis_block(Network::Bitcoin).txdata[0]);
+        let txid = sha256d::Hash::hash(&tx).to_byte_array();
+
+        // the transaction is the leaf at index 0b101 of a tree of depth 3
+        let index = 5;
+        let branch = (0..TX_MERKLE_MAX_DEPTH as u8)
+            .map(|i| H256::repeat_byte(i + 1))
+            .collect::<Vec<_>>();
+        let mut expected_root = txid;
+        for (i, sibling) in branch.iter().take(3).enumerate() {
+            let node = if index & (1 << i) == 0 {
+                [expected_root.as_slice(), sibling.as_bytes()].concat()
+            } else {
+                [sibling.as_bytes(), expected_root.as_slice()].concat()
+            };
+            expected_root = sha256d::Hash::hash(&node).to_byte_array();
+        }
+
+        let mut tx_bytes = [0u8; MAX_TX_BYTES];
+        tx_bytes[..tx.len()].copy_from_slice(&tx);
+
+        let mut input = circuit.input();
+        input.write::<BytesVariable<MAX_TX_BYTES>>(tx_bytes);
+        input.write::<U32Variable>(tx.len() as u32);
+        input.write::<U32Variable>(index);
+        input.write::<U32Variable>(3);
+        input.write::<MerkleBranchVariable>(branch);
+
+        log::debug!("Generating circuit proof");
+        let (proof, mut output) = circuit.prove(&input);
+        circuit.verify(&proof, &input, &output);
+
+        assert_eq!(output.read::<TxidVariable>(), H256(txid));
+        assert_eq!(output.read::<Bytes32Variable>(), H256(expected_root));
+    }
+
+    #[test]
+    fn test_get_tx_output() {
+        env::set_var("RUST_LOG", "debug");
+        env_logger::try_init().unwrap_or_default();
+
+        const MAX_TX_BYTES: usize = 512;
+
+        log::debug!("Defining circuit");
+        let mut builder = DefaultBuilder::new();
+
+        let tx_bytes = builder.read::<BytesVariable<MAX_TX_BYTES>>();
+        let vout = builder.read::<U32Variable>();
+
+        let has_witness = builder.has_witness_marker(&tx_bytes);
+        let output = builder.get_tx_output(&tx_bytes, &vout);
+        let script_hash = builder.calculate_script_hash(&output);
+        builder.write(has_witness);
+        builder.write(output);
+        builder.write(script_hash);
+
+        log::debug!("Building circuit");
+        let circuit = builder.build();
+        log::debug!("Done building circuit");
+
+        // a segwit spend with a second, P2WPKH output
+        let legacy_tx = genesis_block(Network::Bitcoin).txdata[0].clone();
+        let mut segwit_tx = legacy_tx.clone();
+        segwit_tx.input[0].witness.push([0x01; 72]);
+        segwit_tx.output.push(TxOut {
+            value: Amount::from_sat(1234),
+            script_pubkey: ScriptBuf::from_bytes([[0x00, 0x14].as_slice(), &[0xab; 20]].concat()),
+        });
+
+        for (tx, vout, is_segwit) in [(&legacy_tx, 0, false), (&segwit_tx, 1, true)] {
+            let tx_serialized = serialize(tx);
+            let mut tx_bytes = [0u8; MAX_TX_BYTES];
+            tx_bytes[..tx_serialized.len()].copy_from_slice(&tx_serialized);
+
+            let mut input = circuit.input();
+            input.write::<BytesVariable<MAX_TX_BYTES>>(tx_bytes);
+            input.write::<U32Variable>(vout);
+
+            log::debug!("Generating circuit proof");
+            let (proof, mut output) = circuit.prove(&input);
+            circuit.verify(&proof, &input, &output);
+
+            let expected = &tx.output[vout as usize];
+            let script = expected.script_pubkey.as_bytes();
+            let mut expected_script = [0u8; SCRIPT_PUBKEY_MAX_BYTES];
+            expected_script[..script.len()].copy_from_slice(script);
+
+            assert_eq!(output.read::<BoolVariable>(), is_segwit);
+            let tx_output = output.read::<TxOutputVariable>();
+            assert_eq!(tx_output.value, expected.value.to_sat());
+            assert_eq!(tx_output.script_length, script.len() as u32);
+            assert_eq!(tx_output.script, expected_script);
+            assert_eq!(
+                output.read::<Bytes32Variable>(),
+                H256(sha256::Hash::hash(script).to_byte_array())
+            );
+        }
+    }
+
+    #[test]
+    fn test_get_op_return_payload() {
+        env::set_var("RUST_LOG", "debug");
+        env_logger::try_init().unwrap_or_default();
+
+        const MAX_TX_BYTES: usize = 256;
+
+        log::debug!("Defining circuit");
+        let mut builder = DefaultBuilder::new();
+
+        let tx_bytes = builder.read::<BytesVariable<MAX_TX_BYTES>>();
+        let vout = builder.read::<U32Variable>();
+
+        let output = builder.get_tx_output(&tx_bytes, &vout);
+        let (length, payload) = builder.get_op_return_payload(&output);
+        builder.write(length);
+        builder.write(payload);
+
+        log::debug!("Building circuit");
+        let circuit = builder.build();
+        log::debug!("Done building circuit");
+
+        // a 20-byte direct push and an 80-byte OP_PUSHDATA1 push
+        let mut tx = genesis_block(Network::Bitcoin).txdata[0].clone();
+        tx.output[0].script_pubkey = ScriptBuf::new_op_return([0x42; 20]);
+        tx.output.push(TxOut {
+            value: Amount::ZERO,
+            script_pubkey: ScriptBuf::new_op_return(
+                PushBytesBuf::try_from(vec![0x43; OP_RETURN_MAX_PAYLOAD_BYTES]).unwrap(),
+            ),
+        });
+
+        let tx_serialized = serialize(&tx);
+        let mut tx_bytes = [0u8; MAX_TX_BYTES];
+        tx_bytes[..tx_serialized.len()].copy_from_slice(&tx_serialized);
+
+        for (vout, expected_payload) in [(0, [0x42; 20].as_slice()), (1, &[0x43; 80])] {
+            let mut input = circuit.input();
+            input.write::<BytesVariable<MAX_TX_BYTES>>(tx_bytes);
+            input.write::<U32Variable>(vout);
+
+            log::debug!("Generating circuit proof");
+            let (proof, mut output) = circuit.prove(&input);
+            circuit.verify(&proof, &input, &output);
+
+            let mut expected = [0u8; OP_RETURN_MAX_PAYLOAD_BYTES];
+            expected[..expected_payload.len()].copy_from_slice(expected_payload);
+            assert_eq!(output.read::<U32Variable>(), expected_payload.len() as u32);
+            assert_eq!(output.read::<OpReturnPayloadVariable>(), expected);
+        }
+    }
+
+    #[test]
+    fn test_get_coinbase_height() {
+        env::set_var("RUST_LOG", "debug");
+        env_logger::try_init().unwrap_or_default();
+
+        log::debug!("Defining circuit");
+        let mut builder = DefaultBuilder::new();
+
+        let tx_bytes = builder.read::<BytesVariable<COINBASE_MAX_BYTES>>();
+        let height = builder.get_coinbase_height(&tx_bytes);
+        builder.write(height);
+
+        log::debug!("Building circuit");
+        let circuit = builder.build();
+        log::debug!("Done building circuit");
+
+        // OP_N, and pushes of 2, 3 and 4 bytes
+        for height in [5, 1000, 840000, 10_000_000] {
+            let mut tx = genesis_block(Network::Bitcoin).txdata[0].clone();
+            tx.input[0].script_sig = Builder::new()
+                .push_int(height)
+                .push_slice(b"/btcx/")
+                .into_script();
+
+            let tx_serialized = serialize(&tx);
+            let mut tx_bytes = [0u8; COINBASE_MAX_BYTES];
+            tx_bytes[..tx_serialized.len()].copy_from_slice(&tx_serialized);
+
+            let mut input = circuit.input();
+            input.write::<BytesVariable<COINBASE_MAX_BYTES>>(tx_bytes);
+
+            log::debug!("Generating circuit proof for height {}", height);
+            let (proof, mut output) = circuit.prove(&input);
+            circuit.verify(&proof, &input, &output);
+
+            assert_eq!(output.read::<U64Variable>(), height as u64);
+        }
+    }
+
+    #[test]
+    fn test_get_witness_commitment() {
+        env::set_var("RUST_LOG", "debug");
+        env_logger::try_init().unwrap_or_default();
+
+        log::debug!("Defining circuit");
+        let mut builder = DefaultBuilder::new();
+
+        let tx_bytes = builder.read::<BytesVariable<COINBASE_MAX_BYTES>>();
+        let witness_root = builder.read::<Bytes32Variable>();
+        let witness_reserved_value = builder.read::<Bytes32Variable>();
+
+        let commitment = builder.get_witness_commitment(&tx_bytes);
+        builder.verify_witness_commitment(&commitment, &witness_root, &witness_reserved_value);
+        builder.write(commitment);
+
+        log::debug!("Building circuit");
+        let circuit = builder.build();
+        log::debug!("Done building circuit");
+
+        // the commitment is the last of two outputs with the commitment header
+        let witness_root = H256::repeat_byte(0x11);
+        let witness_reserved_value = H256::zero();
+        let commitment = sha256d::Hash::hash(
+            &[witness_root.as_bytes(), witness_reserved_value.as_bytes()].concat(),
+        )
+        .to_byte_array();
+
+        let mut tx = genesis_block(Network::Bitcoin).txdata[0].clone();
+        for commitment in [[0xee; 32], commitment] {
+            tx.output.push(TxOut {
+                value: Amount::ZERO,
+                script_pubkey: ScriptBuf::from_bytes(
+                    [WITNESS_COMMITMENT_HEADER.as_slice(), &commitment].concat(),
+                ),
+            });
+        }
+
+        let tx_serialized = serialize(&tx);
+        let mut tx_bytes = [0u8; COINBASE_MAX_BYTES];
+        tx_bytes[..tx_serialized.len()].copy_from_slice(&tx_serialized);
+
+        let mut input = circuit.input();
+        input.write::<BytesVariable<COINBASE_MAX_BYTES>>(tx_bytes);
+        input.write::<Bytes32Variable>(witness_root);
+        input.write::<Bytes32Variable>(witness_reserved_value);
+
+        log::debug!("Generating circuit proof");
+        let (proof, mut output) = circuit.prove(&input);
+        circuit.verify(&proof, &input, &output);
+
+        assert_eq!(output.read::<Bytes32Variable>(), H256(commitment));
+    }
+}
@@ -6,6 +6,38 @@ pub const HEADER_MANTISSA_FIRST_BYTE_INDEX: usize = 74;
 pub const HEADER_MANTISSA_SECOND_BYTE_INDEX: usize = 73;
 pub const HEADER_MANTISSA_THIRD_BYTE_INDEX: usize = 72;
 
+pub const HEADER_VERSION_INDEX: usize = 0;
 pub const HEADER_PARENT_HASH_INDEX: usize = 4;
 pub const HEADER_MERKLE_ROOT_INDEX: usize = 36;
 pub const HEADER_TIMESTAMP_INDEX: usize = 68;
+
+pub const MEDIAN_TIME_SPAN: usize = 11;
+pub const MAX_FUTURE_BLOCK_TIME: u32 = 2 * 60 * 60;
+pub const MAX_TIMEWARP: u32 = 600;
+
+pub const MMR_MAX_PEAKS: usize = 32;
+
+pub const TX_MERKLE_MAX_DEPTH: usize = 16;
+pub const SHA256_PADDING_MIN_BYTES: usize = 9;
+
+pub const TX_WITNESS_MARKER_INDEX: usize = 4;
+pub const TX_MAX_INPUTS: usize = 16;
+pub const TX_MAX_OUTPUTS: usize = 16;
+pub const TX_OUTPOINT_BYTES_LENGTH: usize = 36;
+pub const TX_SEQUENCE_BYTES_LENGTH: usize = 4;
+pub const TX_VALUE_BYTES_LENGTH: usize = 8;
+pub const SCRIPT_PUBKEY_MAX_BYTES: usize = 128;
+
+pub const OP_RETURN: u8 = 0x6a;
+pub const OP_PUSHDATA1: u8 = 0x4c;
+pub const OP_PUSHBYTES_MAX: u8 = 0x4b;
+pub const OP_RETURN_MAX_PAYLOAD_BYTES: usize = 80;
+
+pub const COINBASE_MAX_BYTES: usize = 512;
+pub const COINBASE_PREVOUT_INDEX: usize = 5;
+pub const COINBASE_SCRIPT_INDEX: usize = 42;
+pub const OP_1: u8 = 0x51;
+pub const OP_16: u8 = 0x60;
+
+pub const WITNESS_COMMITMENT_HEADER: [u8; 6] = [0x6a, 0x24, 0xaa, 0x21, 0xa9, 0xed];
+pub const WITNESS_COMMITMENT_SCRIPT_MIN_BYTES: usize = 38;
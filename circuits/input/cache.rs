@@ -0,0 +1,260 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+
+use bitcoincore_rpc::bitcoin::block::Header;
+use bitcoincore_rpc::bitcoin::consensus::{deserialize, serialize};
+use bitcoincore_rpc::bitcoin::hashes::Hash;
+use bitcoincore_rpc::bitcoin::BlockHash;
+
+use crate::consts::HEADER_BYTES_LENGTH;
+use crate::input::error::{FetchError, FetchResult};
+use crate::input::HeaderSource;
+
+/// Number of blocks below the tip that are never served from or written to the cache.
+pub const DEFAULT_REORG_DEPTH: u64 = 6;
+
+/// Cache record: 8-byte little-endian height, 32-byte block hash, 80-byte header.
+const RECORD_LENGTH: usize = 8 + 32 + HEADER_BYTES_LENGTH;
+
+/// Wraps a header source with a persistent cache of active-chain headers keyed by height
+/// and hash. Headers within `reorg_depth` blocks of the tip always go to the inner source.
+pub struct CachedHeaderSource {
+    pub path: String,
+    pub reorg_depth: u64,
+    inner: Box<dyn HeaderSource>,
+    headers: HashMap<BlockHash, (u64, Header)>,
+    hashes: HashMap<u64, BlockHash>,
+    finalized_height: Option<u64>,
+}
+
+impl CachedHeaderSource {
+    pub fn open(inner: Box<dyn HeaderSource>, path: &str, reorg_depth: u64) -> FetchResult<Self> {
+        let mut source = Self {
+            path: path.to_string(),
+            reorg_depth,
+            inner,
+            headers: HashMap::new(),
+            hashes: HashMap::new(),
+            finalized_height: None,
+        };
+
+        let bytes = match fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(source.io_error(e)),
+        };
+
+        // a partially written trailing record is ignored
+        for record in bytes.chunks_exact(RECORD_LENGTH) {
+            let height = u64::from_le_bytes(record[..8].try_into().unwrap());
+            let hash = BlockHash::from_slice(&record[8..40]).unwrap();
+            let header = deserialize::<Header>(&record[40..])
+                .map_err(|e| FetchError::MalformedHeader(format!("{}: {}", path, e)))?;
+            source.insert(height, hash, header);
+        }
+
+        Ok(source)
+    }
+
+    fn io_error(&self, e: std::io::Error) -> FetchError {
+        FetchError::Transport(format!("{}: {}", self.path, e))
+    }
+
+    fn insert(&mut self, height: u64, hash: BlockHash, header: Header) {
+        if let Some(replaced) = self.hashes.insert(height, hash) {
+            self.headers.remove(&replaced);
+        }
+        self.headers.insert(hash, (height, header));
+    }
+
+    /// Returns the highest height that is cached, dropping cached entries above it.
+    fn finalized_height(&mut self) -> FetchResult<u64> {
+        if let Some(finalized_height) = self.finalized_height {
+            return Ok(finalized_height);
+        }
+
+        let (tip_height, _) = self.inner.get_chain_tip()?;
+        let finalized_height = tip_height.saturating_sub(self.reorg_depth);
+
+        let stale = self
+            .hashes
+            .keys()
+            .filter(|height| **height > finalized_height)
+            .copied()
+            .collect::<Vec<_>>();
+        if !stale.is_empty() {
+            for height in stale {
+                let hash = self.hashes.remove(&height).unwrap();
+                self.headers.remove(&hash);
+            }
+            self.rewrite()?;
+        }
+
+        self.finalized_height = Some(finalized_height);
+        Ok(finalized_height)
+    }
+
+    fn rewrite(&self) -> FetchResult<()> {
+        let mut heights = self.hashes.keys().copied().collect::<Vec<_>>();
+        heights.sort();
+
+        let mut bytes = Vec::with_capacity(heights.len() * RECORD_LENGTH);
+        for height in heights {
+            let hash = self.hashes[&height];
+            bytes.extend(Self::record(height, &hash, &self.headers[&hash].1));
+        }
+
+        fs::write(&self.path, bytes).map_err(|e| self.io_error(e))
+    }
+
+    fn record(height: u64, hash: &BlockHash, header: &Header) -> Vec<u8> {
+        [
+            height.to_le_bytes().to_vec(),
+            hash.to_byte_array().to_vec(),
+            serialize(header),
+        ]
+        .concat()
+    }
+
+    /// Caches headers fetched by height from the inner source, skipping unfinalized ones.
+    fn store(&mut self, start_height: u64, headers: &[Header]) -> FetchResult<()> {
+        let finalized_height = self.finalized_height()?;
+
+        let mut bytes = Vec::new();
+        for (i, header) in headers.iter().enumerate() {
+            let height = start_height + i as u64;
+            if height > finalized_height || self.hashes.contains_key(&height) {
+                continue;
+            }
+
+            let hash = header.block_hash();
+            bytes.extend(Self::record(height, &hash, header));
+            self.insert(height, hash, *header);
+        }
+
+        if bytes.is_empty() {
+            return Ok(());
+        }
+
+        fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .and_then(|mut file| file.write_all(&bytes))
+            .map_err(|e| self.io_error(e))
+    }
+
+    fn cached_by_height(&mut self, height: u64) -> FetchResult<Option<Header>> {
+        if height > self.finalized_height()? {
+            return Ok(None);
+        }
+        Ok(self.hashes.get(&height).map(|hash| self.headers[hash].1))
+    }
+}
+
+impl HeaderSource for CachedHeaderSource {
+    fn get_header_by_height(&mut self, height: u64) -> FetchResult<Header> {
+        if let Some(header) = self.cached_by_height(height)? {
+            return Ok(header);
+        }
+
+        let header = self.inner.get_header_by_height(height)?;
+        self.store(height, &[header])?;
+        Ok(header)
+    }
+
+    fn get_header_by_hash(&mut self, hash: &BlockHash) -> FetchResult<Header> {
+        self.finalized_height()?;
+        match self.headers.get(hash) {
+            Some((_, header)) => Ok(*header),
+            None => self.inner.get_header_by_hash(hash),
+        }
+    }
+
+    fn get_header_height(&mut self, hash: &BlockHash) -> FetchResult<u64> {
+        self.finalized_height()?;
+        match self.headers.get(hash) {
+            Some((height, _)) => Ok(*height),
+            None => self.inner.get_header_height(hash),
+        }
+    }
+
+    fn get_chain_tip(&mut self) -> FetchResult<(u64, BlockHash)> {
+        self.inner.get_chain_tip()
+    }
+
+    fn get_chain_tips(&mut self) -> FetchResult<Vec<(u64, BlockHash)>> {
+        self.inner.get_chain_tips()
+    }
+
+    fn get_headers_by_height(
+        &mut self,
+        start_height: u64,
+        count: usize,
+    ) -> FetchResult<Vec<Header>> {
+        let mut headers = Vec::with_capacity(count);
+
+        // serve the cached prefix, then fetch the rest from the inner source in one request
+        while headers.len() < count {
+            match self.cached_by_height(start_height + headers.len() as u64)? {
+                Some(header) => headers.push(header),
+                None => break,
+            }
+        }
+
+        if headers.len() < count {
+            let fetch_height = start_height + headers.len() as u64;
+            let fetched = self
+                .inner
+                .get_headers_by_height(fetch_height, count - headers.len())?;
+            self.store(fetch_height, &fetched)?;
+            headers.extend(fetched);
+        }
+
+        Ok(headers)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, process};
+
+    use plonky2x::prelude::bytes;
+
+    use super::*;
+    use crate::input::file::FileHeaderSource;
+
+    #[test]
+    fn test_cached_header_source() {
+        let genesis: [u8; HEADER_BYTES_LENGTH] = bytes!("0100000000000000000000000000000000000000000000000000000000000000000000003ba3edfd7a7b12b27ac72c3e67768f617fc81bc3888a51323a9fb8aa4b1e5e4a29ab5f49ffff001d1dac2b7c");
+        let block_1: [u8; HEADER_BYTES_LENGTH] = bytes!("010000006fe28c0ab6f1b372c1a6a246ae63f74f931e8365e15a089c68d6190000000000982051fd1e4ba744bbbe680e1fee14677ba1a3c3540bf7b1cdb606e857233e0e61bc6649ffff001d01e36299");
+        let headers_bytes = [genesis, block_1].concat();
+
+        let path = env::temp_dir().join(format!(
+            "btcx_test_cached_header_source_{}.bin",
+            process::id()
+        ));
+        let path = path.to_str().unwrap();
+        fs::remove_file(path).ok();
+
+        let inner = FileHeaderSource::from_bytes(&headers_bytes, 0).unwrap();
+        let mut source = CachedHeaderSource::open(Box::new(inner), path, 1).unwrap();
+
+        let headers = source.get_headers_by_height(0, 2).unwrap();
+        assert_eq!(serialize(&headers[1]), block_1.to_vec());
+
+        // only the header below the reorg depth is persisted
+        assert_eq!(fs::read(path).unwrap().len(), RECORD_LENGTH);
+
+        let inner = FileHeaderSource::from_bytes(&headers_bytes, 0).unwrap();
+        let mut source = CachedHeaderSource::open(Box::new(inner), path, 1).unwrap();
+        assert_eq!(source.hashes.len(), 1);
+        assert_eq!(
+            serialize(&source.get_header_by_height(0).unwrap()),
+            genesis.to_vec()
+        );
+
+        fs::remove_file(path).unwrap();
+    }
+}
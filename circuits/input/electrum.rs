@@ -0,0 +1,322 @@
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+use bitcoincore_rpc::bitcoin::block::Header;
+use bitcoincore_rpc::bitcoin::consensus::deserialize;
+use bitcoincore_rpc::bitcoin::BlockHash;
+use bitcoincore_rpc::jsonrpc::serde_json::{from_str, from_value, json, Value};
+use serde::Deserialize;
+
+use crate::consts::HEADER_BYTES_LENGTH;
+use crate::input::error::{FetchError, FetchResult};
+use crate::input::HeaderSource;
+
+pub const ELECTRUM_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Maximum number of headers returned by a single `blockchain.block.headers` call.
+pub const ELECTRUM_HEADERS_CHUNK: usize = 2016;
+
+/// Number of blocks below the tip searched when looking up the height of a block hash.
+pub const DEFAULT_SCAN_DEPTH: u64 = 10 * 2016;
+
+const PROTOCOL_VERSION: &str = "1.4";
+
+#[derive(Debug, Deserialize)]
+struct HeadersResult {
+    count: usize,
+    hex: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct TipResult {
+    height: u64,
+    hex: String,
+}
+
+fn transport_error(e: impl ToString) -> FetchError {
+    FetchError::Transport(e.to_string())
+}
+
+fn decode_headers(headers_hex: &str) -> FetchResult<Vec<Header>> {
+    let bytes = hex::decode(headers_hex).map_err(|e| FetchError::MalformedHeader(e.to_string()))?;
+    bytes
+        .chunks(HEADER_BYTES_LENGTH)
+        .map(|header| {
+            deserialize::<Header>(header).map_err(|e| FetchError::MalformedHeader(e.to_string()))
+        })
+        .collect()
+}
+
+/// Serves active-chain headers from an Electrum server (e.g. electrs) over plain TCP.
+/// Electrum has no lookup by hash, so heights of unseen hashes are found by scanning
+/// back at most `scan_depth` blocks from the tip.
+pub struct ElectrumHeaderSource {
+    pub scan_depth: u64,
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    next_id: u64,
+    tip_height: Option<u64>,
+    headers: HashMap<BlockHash, (u64, Header)>,
+}
+
+impl ElectrumHeaderSource {
+    pub fn connect(addr: &str, scan_depth: u64) -> FetchResult<Self> {
+        let stream =
+            TcpStream::connect(addr).map_err(|e| transport_error(format!("{}: {}", addr, e)))?;
+        stream
+            .set_read_timeout(Some(ELECTRUM_TIMEOUT))
+            .map_err(transport_error)?;
+
+        let mut source = Self {
+            scan_depth,
+            reader: BufReader::new(stream.try_clone().map_err(transport_error)?),
+            writer: stream,
+            next_id: 0,
+            tip_height: None,
+            headers: HashMap::new(),
+        };
+        source.call("server.version", json!(["btcx", PROTOCOL_VERSION]))?;
+
+        Ok(source)
+    }
+
+    /// Sends a request and waits for its response, skipping notifications in between.
+    fn call(&mut self, method: &str, params: Value) -> FetchResult<Value> {
+        self.next_id += 1;
+        let request = json!({
+            "jsonrpc": "2.0",
+            "id": self.next_id,
+            "method": method,
+            "params": params,
+        });
+        writeln!(self.writer, "{}", request).map_err(transport_error)?;
+
+        loop {
+            let mut line = String::new();
+            if self.reader.read_line(&mut line).map_err(transport_error)? == 0 {
+                return Err(FetchError::Transport(format!(
+                    "{}: connection closed",
+                    method
+                )));
+            }
+
+            let mut response = from_str::<Value>(&line)
+                .map_err(|e| FetchError::Transport(format!("{}: {}", method, e)))?;
+            if response["id"] != json!(self.next_id) {
+                continue;
+            }
+
+            if !response["error"].is_null() {
+                return Err(FetchError::Transport(format!(
+                    "{}: {}",
+                    method, response["error"]
+                )));
+            }
+            return Ok(response["result"].take());
+        }
+    }
+
+    fn call_as<T: for<'a> Deserialize<'a>>(
+        &mut self,
+        method: &str,
+        params: Value,
+    ) -> FetchResult<T> {
+        from_value(self.call(method, params)?)
+            .map_err(|e| FetchError::Transport(format!("{}: {}", method, e)))
+    }
+
+    fn insert(&mut self, height: u64, header: Header) {
+        self.headers.insert(header.block_hash(), (height, header));
+    }
+
+    /// Fails with `HeightOutOfRange` if `height` is above the last seen tip and the
+    /// current one.
+    fn check_height(&mut self, height: u64) -> FetchResult<()> {
+        let is_above_tip = self
+            .tip_height
+            .map_or(true, |tip_height| height > tip_height);
+        if is_above_tip && height > self.get_chain_tip()?.0 {
+            return Err(FetchError::HeightOutOfRange(height));
+        }
+        Ok(())
+    }
+}
+
+impl HeaderSource for ElectrumHeaderSource {
+    fn get_header_by_height(&mut self, height: u64) -> FetchResult<Header> {
+        self.check_height(height)?;
+
+        let header_hex = self.call_as::<String>("blockchain.block.header", json!([height]))?;
+        let header = decode_headers(&header_hex)?
+            .pop()
+            .ok_or(FetchError::MalformedHeader("empty header".to_string()))?;
+        self.insert(height, header);
+        Ok(header)
+    }
+
+    fn get_header_by_hash(&mut self, hash: &BlockHash) -> FetchResult<Header> {
+        self.get_header_height(hash)?;
+        Ok(self.headers[hash].1)
+    }
+
+    fn get_header_height(&mut self, hash: &BlockHash) -> FetchResult<u64> {
+        if let Some((height, _)) = self.headers.get(hash) {
+            return Ok(*height);
+        }
+
+        let (tip_height, _) = self.get_chain_tip()?;
+        let lowest_height = (tip_height + 1).saturating_sub(self.scan_depth);
+
+        let mut end_height = tip_height + 1;
+        while end_height > lowest_height {
+            let start_height = end_height
+                .saturating_sub(ELECTRUM_HEADERS_CHUNK as u64)
+                .max(lowest_height);
+            self.get_headers_by_height(start_height, (end_height - start_height) as usize)?;
+            if let Some((height, _)) = self.headers.get(hash) {
+                return Ok(*height);
+            }
+            end_height = start_height;
+        }
+
+        Err(FetchError::UnknownHash(*hash))
+    }
+
+    fn get_chain_tip(&mut self) -> FetchResult<(u64, BlockHash)> {
+        let tip = self.call_as::<TipResult>("blockchain.headers.subscribe", json!([]))?;
+        let header = decode_headers(&tip.hex)?
+            .pop()
+            .ok_or(FetchError::MalformedHeader("empty header".to_string()))?;
+        self.insert(tip.height, header);
+
+        self.tip_height = Some(tip.height);
+        Ok((tip.height, header.block_hash()))
+    }
+
+    fn get_headers_by_height(
+        &mut self,
+        start_height: u64,
+        count: usize,
+    ) -> FetchResult<Vec<Header>> {
+        let mut headers = Vec::with_capacity(count);
+
+        while headers.len() < count {
+            let chunk_height = start_height + headers.len() as u64;
+            let chunk_count = (count - headers.len()).min(ELECTRUM_HEADERS_CHUNK);
+
+            let chunk = self.call_as::<HeadersResult>(
+                "blockchain.block.headers",
+                json!([chunk_height, chunk_count]),
+            )?;
+            if chunk.count < chunk_count {
+                return Err(FetchError::HeightOutOfRange(
+                    chunk_height + chunk.count as u64,
+                ));
+            }
+
+            for (i, header) in decode_headers(&chunk.hex)?.into_iter().enumerate() {
+                self.insert(chunk_height + i as u64, header);
+                headers.push(header);
+            }
+        }
+
+        Ok(headers)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+    use std::thread;
+
+    use super::*;
+
+    /// Answers requests over `chain`, sending a tip notification before each response.
+    fn serve_connection(stream: TcpStream, chain: Vec<&'static str>) {
+        let reader = BufReader::new(stream.try_clone().unwrap());
+        let mut writer = stream;
+
+        let tip = json!({"height": chain.len() - 1, "hex": chain[chain.len() - 1]});
+        for line in reader.lines() {
+            let request = from_str::<Value>(&line.unwrap()).unwrap();
+            let params = &request["params"];
+            let result = match request["method"].as_str().unwrap() {
+                "server.version" => json!(["fake", PROTOCOL_VERSION]),
+                "blockchain.headers.subscribe" => tip.clone(),
+                "blockchain.block.header" => json!(chain[params[0].as_u64().unwrap() as usize]),
+                "blockchain.block.headers" => {
+                    let start = params[0].as_u64().unwrap() as usize;
+                    let end = (start + params[1].as_u64().unwrap() as usize).min(chain.len());
+                    json!({"count": end - start, "hex": chain[start..end].concat(), "max": 2016})
+                }
+                method => panic!("unexpected method {}", method),
+            };
+
+            let notification = json!({"jsonrpc": "2.0", "method": "blockchain.headers.subscribe", "params": [tip]});
+            let response = json!({"jsonrpc": "2.0", "id": request["id"], "result": result});
+            writeln!(writer, "{}\n{}", notification, response).unwrap();
+        }
+    }
+
+    /// Runs a fake Electrum server over `chain`.
+    fn serve_fake_server(chain: Vec<&'static str>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let chain = chain.clone();
+                thread::spawn(move || serve_connection(stream.unwrap(), chain));
+            }
+        });
+
+        addr
+    }
+
+    #[test]
+    fn test_electrum_header_source() {
+        let genesis = "0100000000000000000000000000000000000000000000000000000000000000000000003ba3edfd7a7b12b27ac72c3e67768f617fc81bc3888a51323a9fb8aa4b1e5e4a29ab5f49ffff001d1dac2b7c";
+        let block_1 = "010000006fe28c0ab6f1b372c1a6a246ae63f74f931e8365e15a089c68d6190000000000982051fd1e4ba744bbbe680e1fee14677ba1a3c3540bf7b1cdb606e857233e0e61bc6649ffff001d01e36299";
+        let genesis_header = decode_headers(genesis).unwrap()[0];
+        let block_1_header = decode_headers(block_1).unwrap()[0];
+
+        let addr = serve_fake_server(vec![genesis, block_1]);
+        let mut source = ElectrumHeaderSource::connect(&addr, DEFAULT_SCAN_DEPTH).unwrap();
+
+        assert_eq!(source.get_header_by_height(1).unwrap(), block_1_header);
+        assert_eq!(
+            source.get_chain_tip().unwrap(),
+            (1, block_1_header.block_hash())
+        );
+        assert_eq!(
+            source.get_headers_by_height(0, 2).unwrap(),
+            vec![genesis_header, block_1_header]
+        );
+
+        // a fresh connection has to scan back from the tip to find the height
+        let mut source = ElectrumHeaderSource::connect(&addr, DEFAULT_SCAN_DEPTH).unwrap();
+        assert_eq!(
+            source
+                .get_header_by_hash(&genesis_header.block_hash())
+                .unwrap(),
+            genesis_header
+        );
+        assert_eq!(
+            source
+                .get_header_height(&genesis_header.block_hash())
+                .unwrap(),
+            0
+        );
+
+        assert!(matches!(
+            source.get_header_by_height(2),
+            Err(FetchError::HeightOutOfRange(2))
+        ));
+        assert!(matches!(
+            source.get_headers_by_height(1, 2),
+            Err(FetchError::HeightOutOfRange(2))
+        ));
+    }
+}
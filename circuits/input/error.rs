@@ -0,0 +1,61 @@
+use std::fmt;
+
+use bitcoincore_rpc::bitcoin::{BlockHash, Txid};
+
+#[derive(Debug)]
+pub enum FetchError {
+    /// A required configuration value is not set.
+    ConfigMissing(String),
+    /// A configuration value is set but cannot be used.
+    InvalidConfig(String),
+    /// The backend could not be reached or returned an unexpected response.
+    Transport(String),
+    /// The backend does not know a block with this hash.
+    UnknownHash(BlockHash),
+    /// The backend has no block at this height.
+    HeightOutOfRange(u64),
+    /// The backend returned bytes that do not decode to an 80-byte header.
+    MalformedHeader(String),
+    /// The block does not contain a transaction with this txid.
+    UnknownTx { block_hash: BlockHash, txid: Txid },
+    /// The coinbase of the block has no witness reserved value.
+    MissingWitnessCommitment(BlockHash),
+    /// No known branch after `prev_hash` contains enough headers.
+    BranchTooShort {
+        prev_hash: BlockHash,
+        available: usize,
+        required: usize,
+    },
+}
+
+pub type FetchResult<T> = Result<T, FetchError>;
+
+impl fmt::Display for FetchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ConfigMissing(name) => write!(f, "{} is not set in .env", name),
+            Self::InvalidConfig(reason) => write!(f, "invalid configuration: {}", reason),
+            Self::Transport(reason) => write!(f, "header source error: {}", reason),
+            Self::UnknownHash(hash) => write!(f, "unknown block hash {}", hash),
+            Self::HeightOutOfRange(height) => write!(f, "block height {} is out of range", height),
+            Self::MalformedHeader(reason) => write!(f, "malformed header: {}", reason),
+            Self::UnknownTx { block_hash, txid } => {
+                write!(f, "block {} does not contain transaction {}", block_hash, txid)
+            }
+            Self::MissingWitnessCommitment(hash) => {
+                write!(f, "block {} has no witness commitment", hash)
+            }
+            Self::BranchTooShort {
+                prev_hash,
+                available,
+                required,
+            } => write!(
+                f,
+                "branch after block {} has {} headers, {} required",
+                prev_hash, available, required
+            ),
+        }
+    }
+}
+
+impl std::error::Error for FetchError {}
@@ -0,0 +1,283 @@
+use std::str::FromStr;
+use std::time::Duration;
+
+use bitcoincore_rpc::bitcoin::block::{Header, Version};
+use bitcoincore_rpc::bitcoin::consensus::deserialize;
+use bitcoincore_rpc::bitcoin::hashes::Hash;
+use bitcoincore_rpc::bitcoin::{BlockHash, CompactTarget, TxMerkleNode};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+
+use crate::input::error::{FetchError, FetchResult};
+use crate::input::HeaderSource;
+
+pub const ESPLORA_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Number of blocks returned by `/blocks/:start_height`.
+pub const ESPLORA_BLOCKS_PAGE: u64 = 10;
+
+/// Block summary returned by `/block/:hash` and `/blocks/:start_height`, which reports the
+/// height of stale blocks too.
+#[derive(Debug, Deserialize)]
+struct BlockSummary {
+    id: BlockHash,
+    height: u64,
+    version: i32,
+    timestamp: u32,
+    bits: u32,
+    nonce: u32,
+    merkle_root: TxMerkleNode,
+    previousblockhash: Option<BlockHash>,
+}
+
+impl BlockSummary {
+    fn to_header(&self) -> FetchResult<Header> {
+        let header = Header {
+            version: Version::from_consensus(self.version),
+            prev_blockhash: self.previousblockhash.unwrap_or(BlockHash::all_zeros()),
+            merkle_root: self.merkle_root,
+            time: self.timestamp,
+            bits: CompactTarget::from_consensus(self.bits),
+            nonce: self.nonce,
+        };
+        if header.block_hash() != self.id {
+            return Err(FetchError::MalformedHeader(format!(
+                "summary of block {} hashes to {}",
+                self.id,
+                header.block_hash()
+            )));
+        }
+        Ok(header)
+    }
+}
+
+/// Serves headers from an Esplora HTTP API, e.g. `https://blockstream.info/api`.
+pub struct EsploraHeaderSource {
+    pub url: String,
+    agent: ureq::Agent,
+}
+
+impl EsploraHeaderSource {
+    pub fn new(url: &str) -> Self {
+        Self {
+            url: url.trim_end_matches('/').to_string(),
+            agent: ureq::AgentBuilder::new().timeout(ESPLORA_TIMEOUT).build(),
+        }
+    }
+
+    /// Sends a GET request, returning `None` if the resource does not exist.
+    fn get(&self, path: &str) -> FetchResult<Option<ureq::Response>> {
+        match self.agent.get(&format!("{}{}", self.url, path)).call() {
+            Ok(response) => Ok(Some(response)),
+            Err(ureq::Error::Status(404, _)) => Ok(None),
+            Err(e) => Err(FetchError::Transport(format!("GET {}: {}", path, e))),
+        }
+    }
+
+    fn get_text(&self, path: &str) -> FetchResult<Option<String>> {
+        self.get(path)?
+            .map(|response| {
+                response
+                    .into_string()
+                    .map(|text| text.trim().to_string())
+                    .map_err(|e| FetchError::Transport(format!("GET {}: {}", path, e)))
+            })
+            .transpose()
+    }
+
+    fn get_json<T: DeserializeOwned>(&self, path: &str) -> FetchResult<Option<T>> {
+        self.get(path)?
+            .map(|response| {
+                response
+                    .into_json::<T>()
+                    .map_err(|e| FetchError::Transport(format!("GET {}: {}", path, e)))
+            })
+            .transpose()
+    }
+
+    fn parse_hash(path: &str, text: &str) -> FetchResult<BlockHash> {
+        BlockHash::from_str(text)
+            .map_err(|e| FetchError::Transport(format!("GET {}: {}: {}", path, text, e)))
+    }
+}
+
+impl HeaderSource for EsploraHeaderSource {
+    fn get_header_by_height(&mut self, height: u64) -> FetchResult<Header> {
+        let path = format!("/block-height/{}", height);
+        let hash = self
+            .get_text(&path)?
+            .ok_or(FetchError::HeightOutOfRange(height))?;
+        self.get_header_by_hash(&Self::parse_hash(&path, &hash)?)
+    }
+
+    fn get_header_by_hash(&mut self, hash: &BlockHash) -> FetchResult<Header> {
+        let header_hex = self
+            .get_text(&format!("/block/{}/header", hash))?
+            .ok_or(FetchError::UnknownHash(*hash))?;
+        let header_bytes =
+            hex::decode(header_hex).map_err(|e| FetchError::MalformedHeader(e.to_string()))?;
+        deserialize::<Header>(&header_bytes).map_err(|e| FetchError::MalformedHeader(e.to_string()))
+    }
+
+    fn get_header_height(&mut self, hash: &BlockHash) -> FetchResult<u64> {
+        let block = self
+            .get_json::<BlockSummary>(&format!("/block/{}", hash))?
+            .ok_or(FetchError::UnknownHash(*hash))?;
+        Ok(block.height)
+    }
+
+    fn get_chain_tip(&mut self) -> FetchResult<(u64, BlockHash)> {
+        let path = "/blocks/tip/hash";
+        let hash = self
+            .get_text(path)?
+            .ok_or(FetchError::Transport(format!("GET {}: not found", path)))?;
+        let hash = Self::parse_hash(path, &hash)?;
+        Ok((self.get_header_height(&hash)?, hash))
+    }
+
+    fn get_headers_by_height(
+        &mut self,
+        start_height: u64,
+        count: usize,
+    ) -> FetchResult<Vec<Header>> {
+        if count == 0 {
+            return Ok(Vec::new());
+        }
+
+        // each page lists up to ESPLORA_BLOCKS_PAGE blocks downwards from its height
+        let end_height = start_height + count as u64 - 1;
+        let mut headers = Vec::with_capacity(count);
+        let mut page_height = end_height;
+        loop {
+            let blocks = self
+                .get_json::<Vec<BlockSummary>>(&format!("/blocks/{}", page_height))?
+                .unwrap_or_default();
+            let expected_len = (page_height - start_height + 1).min(ESPLORA_BLOCKS_PAGE);
+            for (i, block) in blocks.iter().take(expected_len as usize).enumerate() {
+                if block.height != page_height - i as u64 {
+                    return Err(FetchError::HeightOutOfRange(page_height - i as u64));
+                }
+                headers.push(block.to_header()?);
+            }
+            if (blocks.len() as u64) < expected_len {
+                return Err(FetchError::HeightOutOfRange(
+                    page_height - blocks.len() as u64,
+                ));
+            }
+
+            if page_height - start_height < ESPLORA_BLOCKS_PAGE {
+                break;
+            }
+            page_height -= ESPLORA_BLOCKS_PAGE;
+        }
+        headers.reverse();
+
+        // pages are fetched separately, so a reorg in between would break the links
+        for (i, pair) in headers.windows(2).enumerate() {
+            if pair[1].prev_blockhash != pair[0].block_hash() {
+                return Err(FetchError::Transport(format!(
+                    "chain changed while fetching headers at height {}",
+                    start_height + i as u64 + 1
+                )));
+            }
+        }
+
+        Ok(headers)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    use bitcoincore_rpc::bitcoin::consensus::serialize;
+
+    use super::*;
+
+    const BLOCK_1_SUMMARY: &str = r#"{"id":"00000000839a8e6886ab5951d76f411475428afc90947ee320161bbf18eb6048","height":1,"version":1,"timestamp":1231469665,"tx_count":1,"size":215,"weight":860,"merkle_root":"0e3e2357e806b6cdb1f70b54c3a3a17b6714ee1f0e68bebb44a74b1efd512098","previousblockhash":"000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f","mediantime":1231469665,"nonce":2573394689,"bits":486604799,"difficulty":1}"#;
+    const BLOCKS_1_PAGE: &str = r#"[{"id":"00000000839a8e6886ab5951d76f411475428afc90947ee320161bbf18eb6048","height":1,"version":1,"timestamp":1231469665,"tx_count":1,"size":215,"weight":860,"merkle_root":"0e3e2357e806b6cdb1f70b54c3a3a17b6714ee1f0e68bebb44a74b1efd512098","previousblockhash":"000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f","mediantime":1231469665,"nonce":2573394689,"bits":486604799,"difficulty":1},{"id":"000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f","height":0,"version":1,"timestamp":1231006505,"tx_count":1,"size":285,"weight":1140,"merkle_root":"4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b","previousblockhash":null,"mediantime":1231006505,"nonce":2083236893,"bits":486604799,"difficulty":1}]"#;
+
+    /// Serves recorded Esplora responses on a local port, returning the base url.
+    fn serve_recorded(responses: HashMap<&'static str, &'static str>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+
+                let mut request_line = String::new();
+                let mut reader = BufReader::new(&stream);
+                reader.read_line(&mut request_line).unwrap();
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line.trim().is_empty() {
+                        break;
+                    }
+                }
+
+                let path = request_line.split_whitespace().nth(1).unwrap_or("");
+                let (status, body) = match responses.get(path) {
+                    Some(body) => ("200 OK", *body),
+                    None => ("404 Not Found", "Block not found"),
+                };
+                write!(
+                    stream,
+                    "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                )
+                .unwrap();
+            }
+        });
+
+        url
+    }
+
+    #[test]
+    fn test_esplora_header_source() {
+        let block_1_hash = "00000000839a8e6886ab5951d76f411475428afc90947ee320161bbf18eb6048";
+        let block_1_header = "010000006fe28c0ab6f1b372c1a6a246ae63f74f931e8365e15a089c68d6190000000000982051fd1e4ba744bbbe680e1fee14677ba1a3c3540bf7b1cdb606e857233e0e61bc6649ffff001d01e36299";
+
+        let url = serve_recorded(HashMap::from([
+            ("/block-height/1", block_1_hash),
+            ("/blocks/tip/hash", block_1_hash),
+            (
+                "/block/00000000839a8e6886ab5951d76f411475428afc90947ee320161bbf18eb6048/header",
+                block_1_header,
+            ),
+            (
+                "/block/00000000839a8e6886ab5951d76f411475428afc90947ee320161bbf18eb6048",
+                BLOCK_1_SUMMARY,
+            ),
+            ("/blocks/1", BLOCKS_1_PAGE),
+        ]));
+
+        let mut source = EsploraHeaderSource::new(&url);
+        let hash = BlockHash::from_str(block_1_hash).unwrap();
+
+        let header = source.get_header_by_height(1).unwrap();
+        assert_eq!(hex::encode(serialize(&header)), block_1_header);
+        assert_eq!(header.block_hash(), hash);
+        assert_eq!(source.get_header_height(&hash).unwrap(), 1);
+        assert_eq!(source.get_chain_tip().unwrap(), (1, hash));
+
+        assert!(matches!(
+            source.get_header_by_height(2),
+            Err(FetchError::HeightOutOfRange(2))
+        ));
+
+        let headers = source.get_headers_by_height(0, 2).unwrap();
+        assert_eq!(headers[1], header);
+        assert_eq!(headers[1].prev_blockhash, headers[0].block_hash());
+        assert!(matches!(
+            source.get_headers_by_height(1, 2),
+            Err(FetchError::HeightOutOfRange(2))
+        ));
+    }
+}
@@ -0,0 +1,165 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+
+use bitcoincore_rpc::bitcoin::block::Header;
+use bitcoincore_rpc::bitcoin::consensus::{deserialize, serialize};
+use bitcoincore_rpc::bitcoin::BlockHash;
+
+use crate::consts::HEADER_BYTES_LENGTH;
+use crate::input::error::{FetchError, FetchResult};
+use crate::input::HeaderSource;
+
+/// Serves headers from a flat file of concatenated 80-byte serialized headers,
+/// where the first header in the file is at `start_height`.
+pub struct FileHeaderSource {
+    pub start_height: u64,
+    headers: Vec<Header>,
+    heights: HashMap<BlockHash, u64>,
+}
+
+impl FileHeaderSource {
+    pub fn open(path: &str, start_height: u64) -> FetchResult<Self> {
+        let bytes = fs::read(path)
+            .map_err(|e| FetchError::Transport(format!("failed to read {}: {}", path, e)))?;
+        Self::from_bytes(&bytes, start_height)
+    }
+
+    pub fn from_bytes(bytes: &[u8], start_height: u64) -> FetchResult<Self> {
+        if bytes.len() % HEADER_BYTES_LENGTH != 0 {
+            return Err(FetchError::MalformedHeader(format!(
+                "headers file length {} is not a multiple of {}",
+                bytes.len(),
+                HEADER_BYTES_LENGTH
+            )));
+        }
+
+        let headers = bytes
+            .chunks(HEADER_BYTES_LENGTH)
+            .map(|chunk| {
+                deserialize::<Header>(chunk).map_err(|e| FetchError::MalformedHeader(e.to_string()))
+            })
+            .collect::<FetchResult<Vec<_>>>()?;
+
+        let heights = headers
+            .iter()
+            .enumerate()
+            .map(|(i, header)| (header.block_hash(), start_height + i as u64))
+            .collect();
+
+        Ok(Self {
+            start_height,
+            headers,
+            heights,
+        })
+    }
+}
+
+impl HeaderSource for FileHeaderSource {
+    fn get_header_by_height(&mut self, height: u64) -> FetchResult<Header> {
+        height
+            .checked_sub(self.start_height)
+            .and_then(|index| self.headers.get(index as usize))
+            .copied()
+            .ok_or(FetchError::HeightOutOfRange(height))
+    }
+
+    fn get_header_by_hash(&mut self, hash: &BlockHash) -> FetchResult<Header> {
+        let height = self.get_header_height(hash)?;
+        self.get_header_by_height(height)
+    }
+
+    fn get_header_height(&mut self, hash: &BlockHash) -> FetchResult<u64> {
+        self.heights
+            .get(hash)
+            .copied()
+            .ok_or(FetchError::UnknownHash(*hash))
+    }
+
+    fn get_chain_tip(&mut self) -> FetchResult<(u64, BlockHash)> {
+        let header = self
+            .headers
+            .last()
+            .ok_or(FetchError::HeightOutOfRange(self.start_height))?;
+        Ok((
+            self.start_height + self.headers.len() as u64 - 1,
+            header.block_hash(),
+        ))
+    }
+}
+
+/// Writes headers `start_height..=end_height` from `source` to a flat headers file.
+pub fn export_headers(
+    source: &mut dyn HeaderSource,
+    path: &str,
+    start_height: u64,
+    end_height: u64,
+) -> FetchResult<()> {
+    let io_error = |e: std::io::Error| FetchError::Transport(format!("{}: {}", path, e));
+
+    let mut file = fs::File::create(path).map_err(io_error)?;
+
+    // one retarget period per batch request
+    let mut height = start_height;
+    while height <= end_height {
+        let count = (end_height - height + 1).min(2016) as usize;
+        for header in source.get_headers_by_height(height, count)? {
+            file.write_all(&serialize(&header)).map_err(io_error)?;
+        }
+        height += count as u64;
+
+        log::info!("exported headers up to {}", height - 1);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, process};
+
+    use bitcoincore_rpc::bitcoin::hashes::Hash;
+    use plonky2x::prelude::bytes;
+
+    use super::*;
+    use crate::input::synthetic::{SyntheticChain, REGTEST_BITS};
+
+    #[test]
+    fn test_file_header_source() {
+        let genesis: [u8; HEADER_BYTES_LENGTH] = bytes!("0100000000000000000000000000000000000000000000000000000000000000000000003ba3edfd7a7b12b27ac72c3e67768f617fc81bc3888a51323a9fb8aa4b1e5e4a29ab5f49ffff001d1dac2b7c");
+        let block_1: [u8; HEADER_BYTES_LENGTH] = bytes!("010000006fe28c0ab6f1b372c1a6a246ae63f74f931e8365e15a089c68d6190000000000982051fd1e4ba744bbbe680e1fee14677ba1a3c3540bf7b1cdb606e857233e0e61bc6649ffff001d01e36299");
+
+        let mut source = FileHeaderSource::from_bytes(&[genesis, block_1].concat(), 0).unwrap();
+
+        let block_1_hash = BlockHash::from_byte_array(
+            bytes!("4860eb18bf1b1620e37e9490fc8a427514416fd75159ab86688e9a8300000000"),
+        );
+
+        assert_eq!(source.get_header_height(&block_1_hash).unwrap(), 1);
+        assert_eq!(
+            serialize(&source.get_header_by_height(0).unwrap()),
+            genesis.to_vec()
+        );
+        assert_eq!(serialize(&source.get_header_by_hash(&block_1_hash).unwrap()), block_1.to_vec());
+        assert_eq!(source.get_chain_tip().unwrap(), (1, block_1_hash));
+    }
+
+    #[test]
+    fn test_export_headers() {
+        let mut chain = SyntheticChain::new(0, 1231006505, REGTEST_BITS);
+        chain.extend(4, 600);
+
+        let path = env::temp_dir().join(format!("btcx_test_export_headers_{}.bin", process::id()));
+        let path = path.to_str().unwrap();
+
+        export_headers(&mut chain, path, 1, 4).unwrap();
+        let mut source = FileHeaderSource::open(path, 1).unwrap();
+        fs::remove_file(path).unwrap();
+
+        assert_eq!(
+            source.get_chain_tip().unwrap(),
+            (4, chain.header(4).block_hash())
+        );
+        assert_eq!(source.get_header_by_height(1).unwrap(), chain.header(1));
+    }
+}
@@ -0,0 +1,265 @@
+use bitcoincore_rpc::bitcoin::block::Header;
+use bitcoincore_rpc::bitcoin::consensus::serialize;
+use bitcoincore_rpc::bitcoin::hashes::{sha256d, Hash};
+use bitcoincore_rpc::bitcoin::{Block, BlockHash, Transaction, Txid};
+use ethers::types::H256;
+
+use crate::consts::TX_MERKLE_MAX_DEPTH;
+use crate::input::error::{FetchError, FetchResult};
+use crate::input::rpc::RpcHeaderSource;
+
+/// Path from a transaction to the merkle root of its block. Sibling `i` is on the right
+/// of the node at level `i` if bit `i` of `index` is zero.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MerkleBranch {
+    pub index: u32,
+    pub siblings: Vec<H256>,
+}
+
+impl MerkleBranch {
+    /// Builds the branch of the txid at `index`, duplicating the last node of odd levels
+    /// like Bitcoin does.
+    pub fn new(txids: &[H256], index: usize) -> Self {
+        assert!(
+            index < txids.len(),
+            "index {} is outside of the block",
+            index
+        );
+
+        let mut siblings = Vec::new();
+        let mut nodes = txids.to_vec();
+        let mut position = index;
+        while nodes.len() > 1 {
+            if nodes.len() % 2 == 1 {
+                nodes.push(nodes[nodes.len() - 1]);
+            }
+            siblings.push(nodes[position ^ 1]);
+            nodes = nodes
+                .chunks_exact(2)
+                .map(|pair| merge(&pair[0], &pair[1]))
+                .collect();
+            position >>= 1;
+        }
+
+        Self {
+            index: index as u32,
+            siblings,
+        }
+    }
+
+    pub fn depth(&self) -> u32 {
+        self.siblings.len() as u32
+    }
+
+    pub fn root(&self, txid: &H256) -> H256 {
+        self.siblings
+            .iter()
+            .enumerate()
+            .fold(*txid, |node, (level, sibling)| {
+                if self.index & (1 << level) == 0 {
+                    merge(&node, sibling)
+                } else {
+                    merge(sibling, &node)
+                }
+            })
+    }
+
+    /// Siblings padded with zeros to the depth supported by the circuits. Panics if the
+    /// branch is deeper than `TX_MERKLE_MAX_DEPTH`.
+    pub fn padded_siblings(&self) -> [H256; TX_MERKLE_MAX_DEPTH] {
+        let mut siblings = [H256::zero(); TX_MERKLE_MAX_DEPTH];
+        siblings[..self.siblings.len()].copy_from_slice(&self.siblings);
+        siblings
+    }
+}
+
+fn merge(left: &H256, right: &H256) -> H256 {
+    H256(sha256d::Hash::hash(&[left.as_bytes(), right.as_bytes()].concat()).to_byte_array())
+}
+
+/// Witness of a transaction inclusion proof.
+#[derive(Debug, Clone)]
+pub struct TxInclusionInputs {
+    pub header: Header,
+    /// Transaction serialized without witness data, so that it hashes to the txid.
+    pub tx_bytes: Vec<u8>,
+    pub txid: H256,
+    pub branch: MerkleBranch,
+}
+
+impl TxInclusionInputs {
+    pub fn from_block(block: &Block, txid: &Txid) -> FetchResult<Self> {
+        let txids = block
+            .txdata
+            .iter()
+            .map(|tx| H256(tx.txid().to_byte_array()))
+            .collect::<Vec<_>>();
+
+        let target = H256(txid.to_byte_array());
+        let index = txids
+            .iter()
+            .position(|id| *id == target)
+            .ok_or(FetchError::UnknownTx {
+                block_hash: block.block_hash(),
+                txid: *txid,
+            })?;
+
+        let branch = MerkleBranch::new(&txids, index);
+
+        Ok(Self {
+            header: block.header,
+            tx_bytes: serialize_without_witness(&block.txdata[index]),
+            txid: target,
+            branch,
+        })
+    }
+
+    /// Transaction bytes zero-padded to the buffer size of the circuit.
+    pub fn padded_tx_bytes<const MAX_TX_BYTES: usize>(&self) -> [u8; MAX_TX_BYTES] {
+        let mut bytes = [0u8; MAX_TX_BYTES];
+        bytes[..self.tx_bytes.len()].copy_from_slice(&self.tx_bytes);
+        bytes
+    }
+}
+
+fn serialize_without_witness(tx: &Transaction) -> Vec<u8> {
+    let mut tx = tx.clone();
+    for input in &mut tx.input {
+        input.witness.clear();
+    }
+    serialize(&tx)
+}
+
+/// Witness of a wtxid inclusion proof against the witness commitment of the coinbase.
+#[derive(Debug, Clone)]
+pub struct WitnessInclusionInputs {
+    pub coinbase: TxInclusionInputs,
+    pub witness_reserved_value: H256,
+    /// Transaction serialized with witness data, so that it hashes to the wtxid.
+    pub tx_bytes: Vec<u8>,
+    pub wtxid: H256,
+    pub branch: MerkleBranch,
+}
+
+impl WitnessInclusionInputs {
+    pub fn from_block(block: &Block, txid: &Txid) -> FetchResult<Self> {
+        let coinbase_tx = &block.txdata[0];
+        let coinbase = TxInclusionInputs::from_block(block, &coinbase_tx.txid())?;
+
+        let witness_reserved_value = match coinbase_tx.input[0].witness.nth(0) {
+            Some(value) if value.len() == 32 => H256::from_slice(value),
+            _ => return Err(FetchError::MissingWitnessCommitment(block.block_hash())),
+        };
+
+        let index = block
+            .txdata
+            .iter()
+            .position(|tx| tx.txid() == *txid)
+            .ok_or(FetchError::UnknownTx {
+                block_hash: block.block_hash(),
+                txid: *txid,
+            })?;
+
+        // the coinbase wtxid is replaced by zeros
+        let wtxids = block
+            .txdata
+            .iter()
+            .enumerate()
+            .map(|(i, tx)| match i {
+                0 => H256::zero(),
+                _ => H256(tx.wtxid().to_byte_array()),
+            })
+            .collect::<Vec<_>>();
+
+        Ok(Self {
+            coinbase,
+            witness_reserved_value,
+            tx_bytes: serialize(&block.txdata[index]),
+            wtxid: wtxids[index],
+            branch: MerkleBranch::new(&wtxids, index),
+        })
+    }
+}
+
+/// Fetches the block `block_hash` and builds the inclusion witness of `txid`.
+pub fn get_tx_inclusion_inputs(
+    source: &RpcHeaderSource,
+    block_hash: &BlockHash,
+    txid: &Txid,
+) -> FetchResult<TxInclusionInputs> {
+    let block = source.get_block(block_hash)?;
+    TxInclusionInputs::from_block(&block, txid)
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoincore_rpc::bitcoin::absolute::LockTime;
+    use bitcoincore_rpc::bitcoin::blockdata::constants::genesis_block;
+    use bitcoincore_rpc::bitcoin::Amount;
+    use bitcoincore_rpc::bitcoin::{Network, ScriptBuf, TxOut};
+
+    use super::*;
+    use crate::consts::WITNESS_COMMITMENT_HEADER;
+
+    #[test]
+    fn test_merkle_branch() {
+        // 5 transactions: odd levels duplicate their last node
+        let mut block = genesis_block(Network::Bitcoin);
+        for lock_time in 1..5 {
+            let mut tx = block.txdata[0].clone();
+            tx.lock_time = LockTime::from_consensus(lock_time);
+            block.txdata.push(tx);
+        }
+        let merkle_root = H256(block.compute_merkle_root().unwrap().to_byte_array());
+
+        for tx in &block.txdata {
+            let inputs = TxInclusionInputs::from_block(&block, &tx.txid()).unwrap();
+            assert_eq!(inputs.branch.depth(), 3);
+            assert_eq!(inputs.branch.root(&inputs.txid), merkle_root);
+            assert_eq!(
+                sha256d::Hash::hash(&inputs.tx_bytes).to_byte_array(),
+                inputs.txid.0
+            );
+        }
+
+        let unknown = Txid::from_byte_array([0xff; 32]);
+        assert!(matches!(
+            TxInclusionInputs::from_block(&block, &unknown),
+            Err(FetchError::UnknownTx { .. })
+        ));
+    }
+
+    #[test]
+    fn test_witness_inclusion_inputs() {
+        let mut block = genesis_block(Network::Bitcoin);
+        block.txdata[0].input[0].witness.push([0u8; 32]);
+        for lock_time in 1..4 {
+            let mut tx = block.txdata[0].clone();
+            tx.lock_time = LockTime::from_consensus(lock_time);
+            tx.input[0].witness.push([lock_time as u8; 72]);
+            block.txdata.push(tx);
+        }
+
+        let witness_root = block.witness_root().unwrap();
+        let commitment = Block::compute_witness_commitment(&witness_root, &[0u8; 32]);
+        let script = [WITNESS_COMMITMENT_HEADER.as_slice(), commitment.as_ref()].concat();
+        block.txdata[0].output.push(TxOut {
+            value: Amount::ZERO,
+            script_pubkey: ScriptBuf::from_bytes(script),
+        });
+        block.header.merkle_root = block.compute_merkle_root().unwrap();
+        assert!(block.check_witness_commitment());
+
+        let inputs = WitnessInclusionInputs::from_block(&block, &block.txdata[2].txid()).unwrap();
+        assert_eq!(inputs.branch.index, 2);
+        assert_eq!(
+            inputs.branch.root(&inputs.wtxid),
+            H256(witness_root.to_byte_array())
+        );
+        assert_eq!(
+            sha256d::Hash::hash(&inputs.tx_bytes).to_byte_array(),
+            inputs.wtxid.0
+        );
+        assert_eq!(inputs.coinbase.branch.index, 0);
+    }
+}
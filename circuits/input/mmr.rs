@@ -0,0 +1,123 @@
+use bitcoincore_rpc::bitcoin::hashes::{sha256, Hash};
+use ethers::types::H256;
+
+use crate::consts::MMR_MAX_PEAKS;
+
+/// Native mirror of the Merkle Mountain Range of header hashes built by the circuits. The
+/// peak of height `h` is stored at index `h` and is zero unless bit `h` of `size` is set.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeaderMmr {
+    pub size: u32,
+    pub peaks: [H256; MMR_MAX_PEAKS],
+}
+
+impl Default for HeaderMmr {
+    fn default() -> Self {
+        Self {
+            size: 0,
+            peaks: [H256::zero(); MMR_MAX_PEAKS],
+        }
+    }
+}
+
+impl HeaderMmr {
+    pub fn merge(left: &H256, right: &H256) -> H256 {
+        H256(sha256::Hash::hash(&[left.as_bytes(), right.as_bytes()].concat()).to_byte_array())
+    }
+
+    pub fn from_leaves(leaves: &[H256]) -> Self {
+        let mut mmr = Self::default();
+        for leaf in leaves {
+            mmr.append(*leaf);
+        }
+        mmr
+    }
+
+    pub fn append(&mut self, leaf: H256) {
+        let mut carry = leaf;
+        for height in 0..MMR_MAX_PEAKS {
+            if self.size & (1 << height) == 0 {
+                self.peaks[height] = carry;
+                break;
+            }
+            carry = Self::merge(&self.peaks[height], &carry);
+            self.peaks[height] = H256::zero();
+        }
+        self.size += 1;
+    }
+
+    /// Commitment to the MMR: sha256 of the big-endian size followed by all peaks.
+    pub fn root(&self) -> H256 {
+        let mut bytes = self.size.to_be_bytes().to_vec();
+        for peak in &self.peaks {
+            bytes.extend_from_slice(peak.as_bytes());
+        }
+        H256(sha256::Hash::hash(&bytes).to_byte_array())
+    }
+
+    /// Returns the siblings from the leaf at `position` up to its peak, padded with zeros.
+    pub fn inclusion_proof(leaves: &[H256], position: u32) -> [H256; MMR_MAX_PEAKS] {
+        let size = leaves.len() as u32;
+        assert!(
+            position < size,
+            "position {} is outside of the MMR",
+            position
+        );
+
+        // the peak of the highest height where the size has a bit that the position has not
+        let height = 31 - (size & !position).leading_zeros() as usize;
+        let offset = (position >> height << height) as usize;
+
+        let mut proof = [H256::zero(); MMR_MAX_PEAKS];
+        let mut nodes = leaves[offset..offset + (1 << height)].to_vec();
+        let mut index = position as usize - offset;
+        for sibling in proof.iter_mut().take(height) {
+            *sibling = nodes[index ^ 1];
+            nodes = nodes
+                .chunks_exact(2)
+                .map(|pair| Self::merge(&pair[0], &pair[1]))
+                .collect();
+            index >>= 1;
+        }
+
+        proof
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_header_mmr_append() {
+        let leaves = (0..7u8)
+            .map(|i| H256::repeat_byte(i + 1))
+            .collect::<Vec<_>>();
+
+        let mut mmr = HeaderMmr::from_leaves(&leaves);
+
+        let merge = HeaderMmr::merge;
+        assert_eq!(mmr.size, 7);
+        assert_eq!(mmr.peaks[0], leaves[6]);
+        assert_eq!(mmr.peaks[1], merge(&leaves[4], &leaves[5]));
+        assert_eq!(
+            mmr.peaks[2],
+            merge(
+                &merge(&leaves[0], &leaves[1]),
+                &merge(&leaves[2], &leaves[3])
+            )
+        );
+        assert!(mmr.peaks[3..].iter().all(|peak| peak.is_zero()));
+
+        let proof = HeaderMmr::inclusion_proof(&leaves, 5);
+        assert_eq!(proof[0], leaves[4]);
+        assert!(proof[1..].iter().all(|sibling| sibling.is_zero()));
+        let proof = HeaderMmr::inclusion_proof(&leaves, 2);
+        assert_eq!(proof[..2], [leaves[3], merge(&leaves[0], &leaves[1])]);
+
+        mmr.append(H256::repeat_byte(8));
+        assert_eq!(mmr.size, 8);
+        assert!(mmr.peaks[..3].iter().all(|peak| peak.is_zero()));
+        assert_ne!(mmr.root(), HeaderMmr::default().root());
+    }
+}
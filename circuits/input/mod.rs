@@ -1,91 +1,239 @@
-use std::env;
+pub mod cache;
+pub mod electrum;
+pub mod error;
+pub mod esplora;
+pub mod file;
+pub mod merkle;
+pub mod mmr;
+pub mod p2p;
+pub mod registry;
+pub mod rpc;
+pub mod synthetic;
 
 use bitcoincore_rpc::bitcoin::block::Header;
 use bitcoincore_rpc::bitcoin::hashes::Hash;
 use bitcoincore_rpc::bitcoin::BlockHash;
 use ethers::types::H256;
 
-use bitcoincore_rpc::bitcoin::consensus::{deserialize, serialize};
+use bitcoincore_rpc::bitcoin::consensus::serialize;
 use bitcoincore_rpc::bitcoin::hex::DisplayHex;
-use bitcoincore_rpc::{Auth, Client, RpcApi};
 
-use crate::consts::HEADER_BYTES_LENGTH;
+use crate::consts::{HEADER_BYTES_LENGTH, MEDIAN_TIME_SPAN};
+use crate::input::error::{FetchError, FetchResult};
 
-pub struct InputDataFetcher {
-    pub url: String,
-    pub user: String,
-    pub pass: String,
-}
+/// A backend able to serve Bitcoin block headers to the circuit hints.
+pub trait HeaderSource {
+    fn get_header_by_height(&mut self, height: u64) -> FetchResult<Header>;
+
+    fn get_header_by_hash(&mut self, hash: &BlockHash) -> FetchResult<Header>;
 
-impl Default for InputDataFetcher {
-    fn default() -> Self {
-        dotenv::dotenv().ok();
+    fn get_header_height(&mut self, hash: &BlockHash) -> FetchResult<u64>;
 
-        let rpc_url = env::var("BITCOIN_RPC_URL").expect("BITCOIN_RPC_URL is not set in .env");
-        let rpc_user = env::var("BITCOIN_RPC_USER").expect("BITCOIN_RPC_USER is not set in .env");
-        let rpc_pass = env::var("BITCOIN_RPC_PASS").expect("BITCOIN_RPC_PASS is not set in .env");
+    fn get_chain_tip(&mut self) -> FetchResult<(u64, BlockHash)>;
+
+    /// Returns the tips of all known branches, starting with the active one.
+    fn get_chain_tips(&mut self) -> FetchResult<Vec<(u64, BlockHash)>> {
+        Ok(vec![self.get_chain_tip()?])
+    }
 
-        Self::new(&rpc_url, &rpc_user, &rpc_pass)
+    /// Returns `count` consecutive headers starting at `start_height`. Backends that
+    /// support bulk requests should override this.
+    fn get_headers_by_height(
+        &mut self,
+        start_height: u64,
+        count: usize,
+    ) -> FetchResult<Vec<Header>> {
+        (start_height..start_height + count as u64)
+            .map(|height| self.get_header_by_height(height))
+            .collect()
     }
 }
 
+pub struct InputDataFetcher {
+    pub source: Box<dyn HeaderSource>,
+    /// Tip of the branch to follow when the requested headers are not on the active chain.
+    pub branch_tip: Option<BlockHash>,
+}
+
 impl InputDataFetcher {
-    pub fn new(url: &str, user: &str, pass: &str) -> Self {
+    pub fn new(source: Box<dyn HeaderSource>) -> Self {
         Self {
-            url: url.to_string(),
-            user: user.to_string(),
-            pass: pass.to_string(),
+            source,
+            branch_tip: None,
         }
     }
 
-    fn get_client(&mut self) -> Client {
-        Client::new(
-            &self.url,
-            Auth::UserPass(self.user.to_string(), self.pass.to_string()),
-        )
-        .unwrap()
+    /// Creates a fetcher for the header source and branch configured in the registry.
+    pub fn from_registry() -> FetchResult<Self> {
+        Ok(Self {
+            source: registry::header_source()?,
+            branch_tip: registry::branch_tip()?,
+        })
+    }
+
+    pub fn with_branch_tip(mut self, branch_tip: BlockHash) -> Self {
+        self.branch_tip = Some(branch_tip);
+        self
+    }
+
+    pub fn get_header_by_height(&mut self, block_number: u64) -> FetchResult<Header> {
+        self.source.get_header_by_height(block_number)
+    }
+
+    pub fn get_header_by_hash(&mut self, block_hash: H256) -> FetchResult<Header> {
+        self.source
+            .get_header_by_hash(&BlockHash::from_byte_array(block_hash.0))
+    }
+
+    pub fn to_bytes(&mut self, header: &Header) -> FetchResult<[u8; HEADER_BYTES_LENGTH]> {
+        serialize(header).try_into().map_err(|bytes: Vec<u8>| {
+            FetchError::MalformedHeader(format!("header serialized to {} bytes", bytes.len()))
+        })
+    }
+
+    /// Returns true if the block at `height` on the active chain is `hash`.
+    pub fn is_active(&mut self, hash: &BlockHash, height: u64) -> FetchResult<bool> {
+        let (tip_height, _) = self.source.get_chain_tip()?;
+        Ok(height <= tip_height && self.source.get_header_by_height(height)?.block_hash() == *hash)
     }
 
-    pub fn get_header_by_height(&mut self, block_number: u64) -> Header {
-        let rpc = self.get_client();
-        let hash = rpc.get_block_hash(block_number as u64).unwrap();
-        rpc.get_block_header(&hash).unwrap()
+    /// Returns the ancestor at `ancestor_height` of the block `hash` at `height`, following
+    /// parent links when the block is not on the active chain.
+    pub fn get_ancestor(
+        &mut self,
+        hash: &BlockHash,
+        height: u64,
+        ancestor_height: u64,
+    ) -> FetchResult<Header> {
+        if ancestor_height > height {
+            return Err(FetchError::HeightOutOfRange(ancestor_height));
+        }
+
+        if self.is_active(hash, height)? {
+            return self.source.get_header_by_height(ancestor_height);
+        }
+
+        let mut header = self.source.get_header_by_hash(hash)?;
+        for _ in ancestor_height..height {
+            header = self.source.get_header_by_hash(&header.prev_blockhash)?;
+        }
+        Ok(header)
     }
 
-    pub fn get_header_by_hash(&mut self, block_hash: H256) -> Header {
-        let rpc = self.get_client();
-        let hash = BlockHash::from_slice(block_hash.as_bytes()).unwrap();
-        rpc.get_block_header(&hash).unwrap()
+    /// Returns `count` headers following `prev_hash` on the branch that contains it: the
+    /// active chain if possible, otherwise `branch_tip` or one of the known chain tips.
+    pub fn get_descendants(
+        &mut self,
+        prev_hash: &BlockHash,
+        count: usize,
+    ) -> FetchResult<Vec<Header>> {
+        let prev_height = self.source.get_header_height(prev_hash)?;
+        let end_height = prev_height + count as u64;
+
+        let (active_tip_height, active_tip_hash) = self.source.get_chain_tip()?;
+        let is_prev_active = self.is_active(prev_hash, prev_height)?;
+        if is_prev_active && end_height <= active_tip_height {
+            let headers = self.source.get_headers_by_height(prev_height + 1, count)?;
+            if Self::is_linked(prev_hash, &headers) {
+                return Ok(headers);
+            }
+            log::warn!("active chain changed while fetching headers, following chain tips");
+        }
+
+        let tips = match self.branch_tip {
+            Some(branch_tip) => vec![(self.source.get_header_height(&branch_tip)?, branch_tip)],
+            None => self.source.get_chain_tips()?,
+        };
+
+        let mut longest_branch = 0;
+        for (tip_height, tip_hash) in tips {
+            // the active chain cannot contain descendants of a block that is not on it
+            if tip_height <= prev_height || (!is_prev_active && tip_hash == active_tip_hash) {
+                continue;
+            }
+
+            // walk back from the tip to the first header after prev_hash
+            let mut branch = Vec::new();
+            let mut header = self.source.get_header_by_hash(&tip_hash)?;
+            for _ in prev_height + 1..tip_height {
+                branch.push(header);
+                header = self.source.get_header_by_hash(&header.prev_blockhash)?;
+            }
+            branch.push(header);
+
+            if header.prev_blockhash != *prev_hash {
+                continue;
+            }
+
+            longest_branch = longest_branch.max(branch.len());
+            if branch.len() >= count {
+                branch.reverse();
+                branch.truncate(count);
+                return Ok(branch);
+            }
+        }
+
+        Err(FetchError::BranchTooShort {
+            prev_hash: *prev_hash,
+            available: longest_branch,
+            required: count,
+        })
     }
 
-    pub fn to_bytes(&mut self, header: &Header) -> [u8; HEADER_BYTES_LENGTH] {
-        serialize(header).try_into().unwrap()
+    fn is_linked(prev_hash: &BlockHash, headers: &[Header]) -> bool {
+        let mut prev_hash = *prev_hash;
+        for header in headers {
+            if header.prev_blockhash != prev_hash {
+                return false;
+            }
+            prev_hash = header.block_hash();
+        }
+        true
     }
 
     pub fn get_update_headers_inputs<const UPDATE_HEADERS_COUNT: usize>(
         &mut self,
         prev_header_hash: H256,
-    ) -> Vec<[u8; HEADER_BYTES_LENGTH]> {
-        let rpc = self.get_client();
-
-        let prev_header = rpc.get_block_header_info(
-            &deserialize::<BlockHash>(&prev_header_hash.as_bytes()).unwrap()
-        ).unwrap();
+    ) -> FetchResult<Vec<[u8; HEADER_BYTES_LENGTH]>> {
+        let prev_hash = BlockHash::from_byte_array(prev_header_hash.0);
+        let start_height = self.source.get_header_height(&prev_hash)? + 1;
 
-        let start_height = prev_header.height as u64 + 1;
-        
         let mut update_headers_bytes: Vec<[u8; HEADER_BYTES_LENGTH]> = Vec::new();
 
-        for i in 0..UPDATE_HEADERS_COUNT {
-            let hash = rpc.get_block_hash(start_height + i as u64).unwrap();
-            let header = serialize(
-                &rpc.get_block_header(&hash).unwrap()
-            ).try_into().unwrap();
-            update_headers_bytes.push(header);
+        let headers = self.get_descendants(&prev_hash, UPDATE_HEADERS_COUNT)?;
+        for (i, header) in headers.iter().enumerate() {
+            let header_bytes = self.to_bytes(header)?;
+            update_headers_bytes.push(header_bytes);
+
+            log::debug!(
+                "header {}: {}",
+                start_height + i as u64,
+                header_bytes.as_hex()
+            );
+        }
 
-            log::debug!("header {}: {}", start_height + i as u64, header.as_hex());
+        Ok(update_headers_bytes)
+    }
+
+    /// Returns the `MEDIAN_TIME_SPAN` headers ending with `prev_header_hash`, oldest first.
+    /// Slots before the genesis block are filled with zero bytes.
+    pub fn get_timestamp_context_inputs(
+        &mut self,
+        prev_header_hash: H256,
+    ) -> FetchResult<Vec<[u8; HEADER_BYTES_LENGTH]>> {
+        let mut context_headers_bytes = vec![[0u8; HEADER_BYTES_LENGTH]; MEDIAN_TIME_SPAN];
+
+        let mut hash = BlockHash::from_byte_array(prev_header_hash.0);
+        for header_bytes in context_headers_bytes.iter_mut().rev() {
+            let header = self.source.get_header_by_hash(&hash)?;
+            *header_bytes = self.to_bytes(&header)?;
+
+            hash = header.prev_blockhash;
+            if hash == BlockHash::all_zeros() {
+                break;
+            }
         }
 
-        update_headers_bytes
+        Ok(context_headers_bytes)
     }
 }
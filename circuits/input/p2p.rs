@@ -0,0 +1,436 @@
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::{BufReader, Seek, SeekFrom, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use bitcoincore_rpc::bitcoin::block::Header;
+use bitcoincore_rpc::bitcoin::blockdata::constants::genesis_block;
+use bitcoincore_rpc::bitcoin::consensus::{deserialize, serialize, Decodable};
+use bitcoincore_rpc::bitcoin::hashes::Hash;
+use bitcoincore_rpc::bitcoin::p2p::address::Address;
+use bitcoincore_rpc::bitcoin::p2p::message::{NetworkMessage, RawNetworkMessage};
+use bitcoincore_rpc::bitcoin::p2p::message_blockdata::GetHeadersMessage;
+use bitcoincore_rpc::bitcoin::p2p::message_network::VersionMessage;
+use bitcoincore_rpc::bitcoin::p2p::{Magic, ServiceFlags};
+use bitcoincore_rpc::bitcoin::{BlockHash, Network};
+
+use crate::consts::HEADER_BYTES_LENGTH;
+use crate::input::error::{FetchError, FetchResult};
+use crate::input::HeaderSource;
+
+/// Maximum number of headers a peer returns in a single `headers` message.
+pub const P2P_HEADERS_PER_MESSAGE: usize = 2000;
+
+pub const P2P_TIMEOUT: Duration = Duration::from_secs(30);
+
+const USER_AGENT: &str = "/btcx:0.1.0/";
+
+fn transport_error(e: impl ToString) -> FetchError {
+    FetchError::Transport(e.to_string())
+}
+
+/// Serves active-chain headers downloaded from a Bitcoin peer with `getheaders`. Headers
+/// are synced from the network genesis on demand and kept in memory, and optionally in a
+/// flat headers file so that later sources only sync the headers added since.
+pub struct P2pHeaderSource {
+    pub network: Network,
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    headers: Vec<Header>,
+    heights: HashMap<BlockHash, u64>,
+    synced: bool,
+    headers_path: Option<String>,
+    /// Number of headers from genesis that match the headers file.
+    persisted: usize,
+}
+
+impl P2pHeaderSource {
+    /// Connects to the peer at `addr` and performs the `version`/`verack` handshake.
+    pub fn connect(addr: &str, network: Network) -> FetchResult<Self> {
+        let peer_addr = addr
+            .to_socket_addrs()
+            .map_err(|e| FetchError::InvalidConfig(format!("{}: {}", addr, e)))?
+            .next()
+            .ok_or(FetchError::InvalidConfig(format!("{}: no address", addr)))?;
+
+        let stream =
+            TcpStream::connect_timeout(&peer_addr, P2P_TIMEOUT).map_err(transport_error)?;
+        stream
+            .set_read_timeout(Some(P2P_TIMEOUT))
+            .map_err(transport_error)?;
+        let local_addr = stream.local_addr().map_err(transport_error)?;
+
+        let genesis = genesis_block(network).header;
+        let mut source = Self {
+            network,
+            reader: BufReader::new(stream.try_clone().map_err(transport_error)?),
+            writer: stream,
+            headers: vec![genesis],
+            heights: HashMap::from([(genesis.block_hash(), 0)]),
+            synced: false,
+            headers_path: None,
+            persisted: 0,
+        };
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        source.send(NetworkMessage::Version(VersionMessage::new(
+            ServiceFlags::NONE,
+            now.as_secs() as i64,
+            Address::new(&peer_addr, ServiceFlags::NONE),
+            Address::new(&local_addr, ServiceFlags::NONE),
+            now.as_nanos() as u64,
+            USER_AGENT.to_string(),
+            0,
+        )))?;
+
+        let (mut has_version, mut has_verack) = (false, false);
+        while !(has_version && has_verack) {
+            match source.receive()? {
+                NetworkMessage::Version(_) => {
+                    has_version = true;
+                    source.send(NetworkMessage::Verack)?;
+                }
+                NetworkMessage::Verack => has_verack = true,
+                _ => {}
+            }
+        }
+
+        Ok(source)
+    }
+
+    /// Resumes from the headers in the flat file at `path` and appends newly synced headers
+    /// to it. Headers after the first one that does not connect are discarded, and a file
+    /// that does not start with the genesis of the network is rejected untouched.
+    pub fn with_headers_file(mut self, path: &str) -> FetchResult<Self> {
+        let io_error = |e: std::io::Error| FetchError::Transport(format!("{}: {}", path, e));
+
+        let bytes = match fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(io_error(e)),
+        };
+
+        let mut persisted = 0;
+        for (i, chunk) in bytes.chunks_exact(HEADER_BYTES_LENGTH).enumerate() {
+            let header = deserialize::<Header>(chunk)
+                .map_err(|e| FetchError::MalformedHeader(format!("{}: {}", path, e)))?;
+            if i == 0 {
+                if header != self.headers[0] {
+                    return Err(FetchError::InvalidConfig(format!(
+                        "{}: headers of another network than {}",
+                        path, self.network
+                    )));
+                }
+            } else if header.prev_blockhash == self.headers[i - 1].block_hash() {
+                self.heights.insert(header.block_hash(), i as u64);
+                self.headers.push(header);
+            } else {
+                break;
+            }
+            persisted = i + 1;
+        }
+
+        self.headers_path = Some(path.to_string());
+        self.persisted = persisted;
+        self.persist()?;
+        Ok(self)
+    }
+
+    /// Writes the headers after the last persisted one, truncating reorganized headers.
+    fn persist(&mut self) -> FetchResult<()> {
+        let path = match &self.headers_path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let io_error = |e: std::io::Error| FetchError::Transport(format!("{}: {}", path, e));
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(path)
+            .map_err(io_error)?;
+        file.set_len((self.persisted * HEADER_BYTES_LENGTH) as u64)
+            .map_err(io_error)?;
+        file.seek(SeekFrom::End(0)).map_err(io_error)?;
+
+        let bytes = self.headers[self.persisted..]
+            .iter()
+            .flat_map(serialize)
+            .collect::<Vec<_>>();
+        file.write_all(&bytes).map_err(io_error)?;
+        self.persisted = self.headers.len();
+        Ok(())
+    }
+
+    fn magic(&self) -> Magic {
+        Magic::from(self.network)
+    }
+
+    fn send(&mut self, payload: NetworkMessage) -> FetchResult<()> {
+        let message = RawNetworkMessage::new(self.magic(), payload);
+        self.writer
+            .write_all(&serialize(&message))
+            .map_err(transport_error)
+    }
+
+    /// Reads the next message from the peer, answering pings along the way.
+    fn receive(&mut self) -> FetchResult<NetworkMessage> {
+        loop {
+            let message =
+                RawNetworkMessage::consensus_decode(&mut self.reader).map_err(transport_error)?;
+            if *message.magic() != self.magic() {
+                return Err(FetchError::Transport(format!(
+                    "peer sent message for network {}",
+                    message.magic()
+                )));
+            }
+
+            match message.payload() {
+                NetworkMessage::Ping(nonce) => self.send(NetworkMessage::Pong(*nonce))?,
+                payload => return Ok(payload.clone()),
+            }
+        }
+    }
+
+    /// Block locator for the synced chain: the last 10 hashes, then exponentially sparser.
+    fn locator(&self) -> Vec<BlockHash> {
+        let mut locator = Vec::new();
+        let mut height = self.headers.len() - 1;
+        let mut step = 1;
+        loop {
+            locator.push(self.headers[height].block_hash());
+            if height == 0 {
+                return locator;
+            }
+            if locator.len() >= 10 {
+                step *= 2;
+            }
+            height = height.saturating_sub(step);
+        }
+    }
+
+    /// Requests the next batch of headers, returning false once the peer's tip is reached.
+    fn sync_next(&mut self) -> FetchResult<bool> {
+        self.send(NetworkMessage::GetHeaders(GetHeadersMessage::new(
+            self.locator(),
+            BlockHash::all_zeros(),
+        )))?;
+
+        let headers = loop {
+            if let NetworkMessage::Headers(headers) = self.receive()? {
+                break headers;
+            }
+        };
+
+        if let Some(first) = headers.first() {
+            // the peer continues from the last locator hash on its active chain, which
+            // drops our headers above it if the peer reorganized
+            let fork_height =
+                *self
+                    .heights
+                    .get(&first.prev_blockhash)
+                    .ok_or(FetchError::MalformedHeader(format!(
+                        "peer sent header {} with unknown parent",
+                        first.block_hash()
+                    )))?;
+            for stale in self.headers.drain(fork_height as usize + 1..) {
+                self.heights.remove(&stale.block_hash());
+            }
+            self.persisted = self.persisted.min(self.headers.len());
+        }
+
+        for header in &headers {
+            let hash = header.block_hash();
+            if header.prev_blockhash != self.headers.last().unwrap().block_hash() {
+                return Err(FetchError::MalformedHeader(format!(
+                    "peer sent unconnected header {}",
+                    hash
+                )));
+            }
+            self.heights.insert(hash, self.headers.len() as u64);
+            self.headers.push(*header);
+        }
+
+        Ok(headers.len() == P2P_HEADERS_PER_MESSAGE)
+    }
+
+    /// Syncs headers until `height` is known, or up to the peer's tip if `height` is `None`.
+    fn sync(&mut self, height: Option<u64>) -> FetchResult<()> {
+        while !self.synced && height.map_or(true, |height| height >= self.headers.len() as u64) {
+            self.synced = !self.sync_next()?;
+        }
+        self.persist()
+    }
+}
+
+impl HeaderSource for P2pHeaderSource {
+    fn get_header_by_height(&mut self, height: u64) -> FetchResult<Header> {
+        self.sync(Some(height))?;
+        self.headers
+            .get(height as usize)
+            .copied()
+            .ok_or(FetchError::HeightOutOfRange(height))
+    }
+
+    fn get_header_by_hash(&mut self, hash: &BlockHash) -> FetchResult<Header> {
+        let height = self.get_header_height(hash)?;
+        Ok(self.headers[height as usize])
+    }
+
+    fn get_header_height(&mut self, hash: &BlockHash) -> FetchResult<u64> {
+        if !self.heights.contains_key(hash) {
+            self.sync(None)?;
+        }
+        // only the peer's active chain is known
+        self.heights
+            .get(hash)
+            .copied()
+            .ok_or(FetchError::UnknownHash(*hash))
+    }
+
+    fn get_chain_tip(&mut self) -> FetchResult<(u64, BlockHash)> {
+        self.sync(None)?;
+        let height = self.headers.len() - 1;
+        Ok((height as u64, self.headers[height].block_hash()))
+    }
+
+    fn get_headers_by_height(
+        &mut self,
+        start_height: u64,
+        count: usize,
+    ) -> FetchResult<Vec<Header>> {
+        let end_height = start_height + count as u64;
+        self.sync(Some(end_height.saturating_sub(1)))?;
+        if end_height > self.headers.len() as u64 {
+            return Err(FetchError::HeightOutOfRange(end_height - 1));
+        }
+        Ok(self.headers[start_height as usize..end_height as usize].to_vec())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+    use std::{env, process, thread};
+
+    use plonky2x::prelude::bytes;
+
+    use super::*;
+
+    /// Runs a peer that completes the handshake and answers `getheaders` from `chain`.
+    fn serve_mock_peer(chain: Vec<Header>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut writer = stream;
+            let mut send = |payload: NetworkMessage| {
+                let message = RawNetworkMessage::new(Magic::BITCOIN, payload);
+                writer.write_all(&serialize(&message)).unwrap();
+            };
+
+            while let Ok(message) = RawNetworkMessage::consensus_decode(&mut reader) {
+                match message.payload() {
+                    NetworkMessage::Version(version) => {
+                        send(NetworkMessage::Version(version.clone()));
+                        send(NetworkMessage::Ping(7));
+                        send(NetworkMessage::Verack);
+                    }
+                    NetworkMessage::GetHeaders(request) => {
+                        let start = chain
+                            .iter()
+                            .position(|header| header.block_hash() == request.locator_hashes[0])
+                            .unwrap();
+                        send(NetworkMessage::Headers(chain[start + 1..].to_vec()));
+                    }
+                    _ => {}
+                }
+            }
+        });
+
+        addr
+    }
+
+    #[test]
+    fn test_p2p_header_source() {
+        let block_1: [u8; HEADER_BYTES_LENGTH] = bytes!("010000006fe28c0ab6f1b372c1a6a246ae63f74f931e8365e15a089c68d6190000000000982051fd1e4ba744bbbe680e1fee14677ba1a3c3540bf7b1cdb606e857233e0e61bc6649ffff001d01e36299");
+        let block_1 = deserialize::<Header>(&block_1).unwrap();
+        let genesis = genesis_block(Network::Bitcoin).header;
+
+        let addr = serve_mock_peer(vec![genesis, block_1]);
+        let mut source = P2pHeaderSource::connect(&addr, Network::Bitcoin).unwrap();
+
+        assert_eq!(source.get_header_by_height(1).unwrap(), block_1);
+        assert_eq!(
+            source.get_headers_by_height(0, 2).unwrap(),
+            vec![genesis, block_1]
+        );
+        assert_eq!(source.get_chain_tip().unwrap(), (1, block_1.block_hash()));
+        assert_eq!(source.get_header_height(&block_1.block_hash()).unwrap(), 1);
+
+        assert!(matches!(
+            source.get_header_by_height(2),
+            Err(FetchError::HeightOutOfRange(2))
+        ));
+    }
+
+    #[test]
+    fn test_p2p_headers_file() {
+        let block_1: [u8; HEADER_BYTES_LENGTH] = bytes!("010000006fe28c0ab6f1b372c1a6a246ae63f74f931e8365e15a089c68d6190000000000982051fd1e4ba744bbbe680e1fee14677ba1a3c3540bf7b1cdb606e857233e0e61bc6649ffff001d01e36299");
+        let block_1 = deserialize::<Header>(&block_1).unwrap();
+        let genesis = genesis_block(Network::Bitcoin).header;
+
+        let path =
+            env::temp_dir().join(format!("btcx_test_p2p_headers_file_{}.bin", process::id()));
+        let path = path.to_str().unwrap();
+        fs::remove_file(path).ok();
+
+        let addr = serve_mock_peer(vec![genesis, block_1]);
+        let mut source = P2pHeaderSource::connect(&addr, Network::Bitcoin)
+            .unwrap()
+            .with_headers_file(path)
+            .unwrap();
+        source.get_chain_tip().unwrap();
+        assert_eq!(
+            fs::read(path).unwrap(),
+            [serialize(&genesis), serialize(&block_1)].concat()
+        );
+
+        // a new source resumes from the file instead of syncing from genesis
+        let addr = serve_mock_peer(vec![genesis, block_1]);
+        let source = P2pHeaderSource::connect(&addr, Network::Bitcoin)
+            .unwrap()
+            .with_headers_file(path)
+            .unwrap();
+        assert_eq!(source.headers, vec![genesis, block_1]);
+        assert_eq!(source.heights[&block_1.block_hash()], 1);
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_p2p_headers_file_of_another_network() {
+        let path = env::temp_dir().join(format!(
+            "btcx_test_p2p_headers_file_of_another_network_{}.bin",
+            process::id()
+        ));
+        let path = path.to_str().unwrap();
+        let regtest_headers = serialize(&genesis_block(Network::Regtest).header);
+        fs::write(path, &regtest_headers).unwrap();
+
+        let addr = serve_mock_peer(vec![genesis_block(Network::Bitcoin).header]);
+        let result = P2pHeaderSource::connect(&addr, Network::Bitcoin)
+            .unwrap()
+            .with_headers_file(path);
+        assert!(matches!(result, Err(FetchError::InvalidConfig(_))));
+        assert_eq!(fs::read(path).unwrap(), regtest_headers);
+
+        fs::remove_file(path).unwrap();
+    }
+}
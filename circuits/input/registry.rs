@@ -0,0 +1,235 @@
+use std::cell::RefCell;
+use std::env;
+use std::str::FromStr;
+use std::sync::RwLock;
+
+use bitcoincore_rpc::bitcoin::{BlockHash, Network};
+
+use crate::input::cache::{CachedHeaderSource, DEFAULT_REORG_DEPTH};
+use crate::input::electrum::{self, ElectrumHeaderSource};
+use crate::input::error::{FetchError, FetchResult};
+use crate::input::esplora::EsploraHeaderSource;
+use crate::input::file::FileHeaderSource;
+use crate::input::p2p::P2pHeaderSource;
+use crate::input::rpc::RpcHeaderSource;
+use crate::input::HeaderSource;
+
+type HeaderSourceFactory = Box<dyn Fn() -> FetchResult<Box<dyn HeaderSource>> + Send + Sync>;
+
+static HEADER_SOURCE_FACTORY: RwLock<Option<HeaderSourceFactory>> = RwLock::new(None);
+
+thread_local! {
+    static SCOPED_HEADER_SOURCE_FACTORY: RefCell<Option<HeaderSourceFactory>> = RefCell::new(None);
+}
+
+/// Overrides the header source used by the circuit hints.
+pub fn register_header_source<F>(factory: F)
+where
+    F: Fn() -> FetchResult<Box<dyn HeaderSource>> + Send + Sync + 'static,
+{
+    *HEADER_SOURCE_FACTORY.write().unwrap() = Some(Box::new(factory));
+}
+
+/// Restores the default, environment-configured header source.
+pub fn reset_header_source() {
+    *HEADER_SOURCE_FACTORY.write().unwrap() = None;
+}
+
+/// Runs `f` with `factory` as the header source for hints executed on the current thread,
+/// taking precedence over the registered one. Lets tests run side by side with their own
+/// chains.
+pub fn with_header_source<F, R>(factory: F, f: impl FnOnce() -> R) -> R
+where
+    F: Fn() -> FetchResult<Box<dyn HeaderSource>> + Send + Sync + 'static,
+{
+    struct Restore(Option<HeaderSourceFactory>);
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            SCOPED_HEADER_SOURCE_FACTORY.with(|scoped| *scoped.borrow_mut() = self.0.take());
+        }
+    }
+
+    let _restore = Restore(
+        SCOPED_HEADER_SOURCE_FACTORY.with(|scoped| scoped.borrow_mut().replace(Box::new(factory))),
+    );
+    f()
+}
+
+/// Returns the scoped or registered header source, falling back to `HeaderSourceConfig::from_env`
+/// wrapped in the on-disk cache if `HEADERS_CACHE` is set.
+pub fn header_source() -> FetchResult<Box<dyn HeaderSource>> {
+    if let Some(source) = SCOPED_HEADER_SOURCE_FACTORY
+        .with(|scoped| scoped.borrow().as_ref().map(|factory| factory()))
+    {
+        return source;
+    }
+
+    if let Some(factory) = HEADER_SOURCE_FACTORY.read().unwrap().as_ref() {
+        return factory();
+    }
+
+    let source = HeaderSourceConfig::from_env()?.build()?;
+    match CacheConfig::from_env()? {
+        Some(cache) => cache.wrap(source),
+        None => Ok(source),
+    }
+}
+
+/// Returns the branch tip to follow for headers off the active chain, if configured.
+pub fn branch_tip() -> FetchResult<Option<BlockHash>> {
+    dotenv::dotenv().ok();
+
+    env::var("HEADERS_BRANCH_TIP")
+        .ok()
+        .map(|tip| {
+            BlockHash::from_str(&tip).map_err(|e| {
+                FetchError::InvalidConfig(format!("HEADERS_BRANCH_TIP {}: {}", tip, e))
+            })
+        })
+        .transpose()
+}
+
+fn required_var(name: &str) -> FetchResult<String> {
+    env::var(name).map_err(|_| FetchError::ConfigMissing(name.to_string()))
+}
+
+fn parse_var<T: FromStr>(name: &str, default: T) -> FetchResult<T>
+where
+    T::Err: std::fmt::Display,
+{
+    match env::var(name) {
+        Ok(value) => value
+            .parse()
+            .map_err(|e| FetchError::InvalidConfig(format!("{} {}: {}", name, value, e))),
+        Err(_) => Ok(default),
+    }
+}
+
+/// Headers file of the P2P source unless `BITCOIN_P2P_HEADERS` is set, so that each proof
+/// only syncs the headers added since the last one.
+fn default_p2p_headers_path(network: Network) -> String {
+    env::temp_dir()
+        .join(format!("btcx_p2p_headers_{}.bin", network))
+        .to_string_lossy()
+        .to_string()
+}
+
+#[derive(Debug, Clone)]
+pub struct CacheConfig {
+    pub path: String,
+    pub reorg_depth: u64,
+}
+
+impl CacheConfig {
+    pub fn from_env() -> FetchResult<Option<Self>> {
+        dotenv::dotenv().ok();
+
+        match env::var("HEADERS_CACHE") {
+            Ok(path) => Ok(Some(Self {
+                path,
+                reorg_depth: parse_var("HEADERS_CACHE_REORG_DEPTH", DEFAULT_REORG_DEPTH)?,
+            })),
+            Err(_) => Ok(None),
+        }
+    }
+
+    pub fn wrap(&self, source: Box<dyn HeaderSource>) -> FetchResult<Box<dyn HeaderSource>> {
+        Ok(Box::new(CachedHeaderSource::open(
+            source,
+            &self.path,
+            self.reorg_depth,
+        )?))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum HeaderSourceConfig {
+    Rpc {
+        url: String,
+        user: String,
+        pass: String,
+    },
+    File {
+        path: String,
+        start_height: u64,
+    },
+    Esplora {
+        url: String,
+    },
+    P2p {
+        addr: String,
+        network: Network,
+        headers_path: String,
+    },
+    Electrum {
+        addr: String,
+        scan_depth: u64,
+    },
+}
+
+impl HeaderSourceConfig {
+    pub fn from_env() -> FetchResult<Self> {
+        dotenv::dotenv().ok();
+
+        let kind = env::var("HEADER_SOURCE").unwrap_or("rpc".to_string());
+
+        match kind.as_str() {
+            "rpc" => Self::rpc_from_env(),
+            "file" => Ok(Self::File {
+                path: required_var("HEADERS_FILE")?,
+                start_height: parse_var("HEADERS_FILE_START_HEIGHT", 0)?,
+            }),
+            "esplora" => Ok(Self::Esplora {
+                url: required_var("ESPLORA_URL")?,
+            }),
+            "p2p" => {
+                let network = parse_var("BITCOIN_P2P_NETWORK", Network::Bitcoin)?;
+                Ok(Self::P2p {
+                    addr: required_var("BITCOIN_P2P_ADDR")?,
+                    network,
+                    headers_path: env::var("BITCOIN_P2P_HEADERS")
+                        .unwrap_or_else(|_| default_p2p_headers_path(network)),
+                })
+            }
+            "electrum" => Ok(Self::Electrum {
+                addr: required_var("ELECTRUM_ADDR")?,
+                scan_depth: parse_var("ELECTRUM_SCAN_DEPTH", electrum::DEFAULT_SCAN_DEPTH)?,
+            }),
+            _ => Err(FetchError::InvalidConfig(format!(
+                "unsupported HEADER_SOURCE {}",
+                kind
+            ))),
+        }
+    }
+
+    pub fn rpc_from_env() -> FetchResult<Self> {
+        dotenv::dotenv().ok();
+
+        Ok(Self::Rpc {
+            url: required_var("BITCOIN_RPC_URL")?,
+            user: required_var("BITCOIN_RPC_USER")?,
+            pass: required_var("BITCOIN_RPC_PASS")?,
+        })
+    }
+
+    pub fn build(&self) -> FetchResult<Box<dyn HeaderSource>> {
+        Ok(match self {
+            Self::Rpc { url, user, pass } => Box::new(RpcHeaderSource::new(url, user, pass)?),
+            Self::File { path, start_height } => {
+                Box::new(FileHeaderSource::open(path, *start_height)?)
+            }
+            Self::Esplora { url } => Box::new(EsploraHeaderSource::new(url)),
+            Self::P2p {
+                addr,
+                network,
+                headers_path,
+            } => {
+                Box::new(P2pHeaderSource::connect(addr, *network)?.with_headers_file(headers_path)?)
+            }
+            Self::Electrum { addr, scan_depth } => {
+                Box::new(ElectrumHeaderSource::connect(addr, *scan_depth)?)
+            }
+        })
+    }
+}
@@ -0,0 +1,185 @@
+use bitcoincore_rpc::bitcoin::block::Header;
+use bitcoincore_rpc::bitcoin::consensus::deserialize;
+use bitcoincore_rpc::bitcoin::{Block, BlockHash};
+use bitcoincore_rpc::json::GetChainTipsResultStatus;
+use bitcoincore_rpc::jsonrpc;
+use bitcoincore_rpc::{Auth, Client, RpcApi};
+
+use crate::input::error::{FetchError, FetchResult};
+use crate::input::HeaderSource;
+
+/// Maximum number of calls sent in a single JSON-RPC batch request.
+pub const RPC_BATCH_SIZE: usize = 500;
+
+// bitcoind error codes, see `src/rpc/protocol.h`
+const RPC_INVALID_ADDRESS_OR_KEY: i32 = -5;
+const RPC_INVALID_PARAMETER: i32 = -8;
+
+pub struct RpcHeaderSource {
+    client: Client,
+}
+
+impl RpcHeaderSource {
+    pub fn new(url: &str, user: &str, pass: &str) -> FetchResult<Self> {
+        let client = Client::new(url, Auth::UserPass(user.to_string(), pass.to_string()))
+            .map_err(transport_error)?;
+        Ok(Self { client })
+    }
+
+    /// Returns the full block, fetched with `getblock` at verbosity 0.
+    pub fn get_block(&self, hash: &BlockHash) -> FetchResult<Block> {
+        self.client
+            .get_block(hash)
+            .map_err(|e| client_error(e, |e| hash_error(*hash, e)))
+    }
+
+    fn batch_call<T: for<'a> serde::de::Deserialize<'a>>(
+        &self,
+        method: &str,
+        params: &[Vec<Box<jsonrpc::serde_json::value::RawValue>>],
+    ) -> FetchResult<Vec<Result<T, jsonrpc::Error>>> {
+        let rpc = self.client.get_jsonrpc_client();
+
+        let requests = params
+            .iter()
+            .map(|p| rpc.build_request(method, p))
+            .collect::<Vec<_>>();
+
+        rpc.send_batch(&requests)
+            .map_err(transport_error)?
+            .into_iter()
+            .map(|response| {
+                response
+                    .map(|response| response.result())
+                    .ok_or(FetchError::Transport(format!(
+                        "missing response in {} batch",
+                        method
+                    )))
+            })
+            .collect()
+    }
+}
+
+fn transport_error(e: impl ToString) -> FetchError {
+    FetchError::Transport(e.to_string())
+}
+
+fn rpc_error_code(e: &jsonrpc::Error) -> Option<i32> {
+    match e {
+        jsonrpc::Error::Rpc(e) => Some(e.code),
+        _ => None,
+    }
+}
+
+fn height_error(height: u64, e: jsonrpc::Error) -> FetchError {
+    match rpc_error_code(&e) {
+        Some(RPC_INVALID_PARAMETER) => FetchError::HeightOutOfRange(height),
+        _ => transport_error(e),
+    }
+}
+
+fn hash_error(hash: BlockHash, e: jsonrpc::Error) -> FetchError {
+    match rpc_error_code(&e) {
+        Some(RPC_INVALID_ADDRESS_OR_KEY) => FetchError::UnknownHash(hash),
+        _ => transport_error(e),
+    }
+}
+
+fn client_error(
+    e: bitcoincore_rpc::Error,
+    on_rpc_error: impl FnOnce(jsonrpc::Error) -> FetchError,
+) -> FetchError {
+    match e {
+        bitcoincore_rpc::Error::JsonRpc(e) => on_rpc_error(e),
+        e => transport_error(e),
+    }
+}
+
+impl HeaderSource for RpcHeaderSource {
+    fn get_header_by_height(&mut self, height: u64) -> FetchResult<Header> {
+        let hash = self
+            .client
+            .get_block_hash(height)
+            .map_err(|e| client_error(e, |e| height_error(height, e)))?;
+        self.get_header_by_hash(&hash)
+    }
+
+    fn get_header_by_hash(&mut self, hash: &BlockHash) -> FetchResult<Header> {
+        self.client
+            .get_block_header(hash)
+            .map_err(|e| client_error(e, |e| hash_error(*hash, e)))
+    }
+
+    fn get_header_height(&mut self, hash: &BlockHash) -> FetchResult<u64> {
+        let info = self
+            .client
+            .get_block_header_info(hash)
+            .map_err(|e| client_error(e, |e| hash_error(*hash, e)))?;
+        Ok(info.height as u64)
+    }
+
+    fn get_chain_tip(&mut self) -> FetchResult<(u64, BlockHash)> {
+        let hash = self.client.get_best_block_hash().map_err(transport_error)?;
+        Ok((self.get_header_height(&hash)?, hash))
+    }
+
+    fn get_chain_tips(&mut self) -> FetchResult<Vec<(u64, BlockHash)>> {
+        let mut tips = self
+            .client
+            .get_chain_tips()
+            .map_err(transport_error)?
+            .into_iter()
+            .filter(|tip| tip.status != GetChainTipsResultStatus::Invalid)
+            .collect::<Vec<_>>();
+        tips.sort_by_key(|tip| tip.status != GetChainTipsResultStatus::Active);
+
+        Ok(tips.into_iter().map(|tip| (tip.height, tip.hash)).collect())
+    }
+
+    fn get_headers_by_height(
+        &mut self,
+        start_height: u64,
+        count: usize,
+    ) -> FetchResult<Vec<Header>> {
+        let mut headers = Vec::with_capacity(count);
+
+        let heights = (start_height..start_height + count as u64).collect::<Vec<_>>();
+        for chunk in heights.chunks(RPC_BATCH_SIZE) {
+            let hashes = self
+                .batch_call::<BlockHash>(
+                    "getblockhash",
+                    &chunk
+                        .iter()
+                        .map(|height| vec![jsonrpc::arg(height)])
+                        .collect::<Vec<_>>(),
+                )?
+                .into_iter()
+                .zip(chunk)
+                .map(|(hash, height)| hash.map_err(|e| height_error(*height, e)))
+                .collect::<FetchResult<Vec<_>>>()?;
+
+            let headers_hex = self
+                .batch_call::<String>(
+                    "getblockheader",
+                    &hashes
+                        .iter()
+                        .map(|hash| vec![jsonrpc::arg(hash), jsonrpc::arg(false)])
+                        .collect::<Vec<_>>(),
+                )?
+                .into_iter()
+                .zip(&hashes)
+                .map(|(header_hex, hash)| header_hex.map_err(|e| hash_error(*hash, e)))
+                .collect::<FetchResult<Vec<_>>>()?;
+
+            for header_hex in headers_hex {
+                let header_bytes = hex::decode(&header_hex)
+                    .map_err(|e| FetchError::MalformedHeader(e.to_string()))?;
+                let header = deserialize::<Header>(&header_bytes)
+                    .map_err(|e| FetchError::MalformedHeader(e.to_string()))?;
+                headers.push(header);
+            }
+        }
+
+        Ok(headers)
+    }
+}
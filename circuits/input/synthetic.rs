@@ -0,0 +1,315 @@
+use std::collections::HashMap;
+
+use bitcoincore_rpc::bitcoin::block::{Header, Version};
+use bitcoincore_rpc::bitcoin::blockdata::constants::genesis_block;
+use bitcoincore_rpc::bitcoin::blockdata::script::Builder;
+use bitcoincore_rpc::bitcoin::hashes::Hash;
+use bitcoincore_rpc::bitcoin::{
+    Block, BlockHash, CompactTarget, Network, Target, Transaction, TxMerkleNode,
+};
+use ethers::types::{H256, U256};
+use num_bigint::BigUint;
+
+use crate::input::error::{FetchError, FetchResult};
+use crate::input::{registry, HeaderSource};
+use crate::params::ChainParams;
+use crate::utils::retarget_threshold;
+
+/// nBits of the easiest target allowed on regtest.
+pub const REGTEST_BITS: u32 = 0x207fffff;
+
+/// Version of the mined headers, with the BIP9 version bits set.
+pub const SYNTHETIC_VERSION: i32 = 0x20000000;
+
+pub fn bits_to_target(bits: u32) -> BigUint {
+    BigUint::from_bytes_be(&Target::from_compact(CompactTarget::from_consensus(bits)).to_be_bytes())
+}
+
+pub fn target_to_bits(target: &BigUint) -> u32 {
+    let mut target_bytes = [0u8; 32];
+    let bytes = target.to_bytes_be();
+    target_bytes[32 - bytes.len()..].copy_from_slice(&bytes);
+    Target::from_be_bytes(target_bytes)
+        .to_compact_lossy()
+        .to_consensus()
+}
+
+/// Coinbase of the synthetic block at `height`, pushing the height as BIP34 requires and
+/// the timestamp so that chains forked from the same parent differ.
+pub fn synthetic_coinbase(height: u64, time: u32) -> Transaction {
+    let mut tx = genesis_block(Network::Regtest).txdata[0].clone();
+    tx.input[0].script_sig = Builder::new()
+        .push_int(height as i64)
+        .push_int(time as i64)
+        .into_script();
+    tx
+}
+
+/// A deterministic header chain mined at easy targets, for testing the circuits offline.
+/// Headers carry valid SHA256d proof of work and are served as a `HeaderSource`.
+#[derive(Debug, Clone)]
+pub struct SyntheticChain {
+    pub start_height: u64,
+    /// Number of blocks between retargets, or `None` to keep nBits constant.
+    pub retarget_interval: Option<u64>,
+    pub target_spacing: u32,
+    pub pow_limit: BigUint,
+    /// Mines late blocks at the minimum difficulty, like testnet.
+    pub min_difficulty_blocks: bool,
+    /// Retargets from the first block of the period instead of the last.
+    pub enforce_bip94: bool,
+    headers: Vec<Header>,
+    heights: HashMap<BlockHash, u64>,
+}
+
+impl SyntheticChain {
+    /// Starts a chain with an anchor block at `start_height`, retargeting every 2016
+    /// blocks up to the regtest pow limit.
+    pub fn new(start_height: u64, time: u32, bits: u32) -> Self {
+        let mut chain = Self {
+            start_height,
+            retarget_interval: Some(2016),
+            target_spacing: 600,
+            pow_limit: BigUint::from_bytes_be(&[[0x7f].as_slice(), &[0xff; 31]].concat()),
+            min_difficulty_blocks: false,
+            enforce_bip94: false,
+            headers: Vec::new(),
+            heights: HashMap::new(),
+        };
+        chain.mine(time, bits);
+        chain
+    }
+
+    pub fn with_retarget_interval(mut self, retarget_interval: Option<u64>) -> Self {
+        self.retarget_interval = retarget_interval;
+        self
+    }
+
+    pub fn with_pow_limit(mut self, pow_limit: BigUint) -> Self {
+        self.pow_limit = pow_limit;
+        self
+    }
+
+    /// Follows the retarget rules of `P` for the blocks mined after the anchor.
+    pub fn with_params<P: ChainParams>(mut self) -> Self {
+        self.retarget_interval =
+            (!P::POW_NO_RETARGETING).then_some(P::DIFFICULTY_ADJUSTMENT_INTERVAL);
+        self.target_spacing = P::POW_TARGET_SPACING;
+        self.pow_limit = P::pow_limit();
+        self.min_difficulty_blocks = P::POW_ALLOW_MIN_DIFFICULTY_BLOCKS;
+        self.enforce_bip94 = P::ENFORCE_BIP94;
+        self
+    }
+
+    pub fn tip_height(&self) -> u64 {
+        self.start_height + self.headers.len() as u64 - 1
+    }
+
+    pub fn header(&self, height: u64) -> Header {
+        self.headers[(height - self.start_height) as usize]
+    }
+
+    /// Block at `height`, with its coinbase as the only transaction.
+    pub fn block(&self, height: u64) -> Block {
+        let header = self.header(height);
+        Block {
+            header,
+            txdata: vec![synthetic_coinbase(height, header.time)],
+        }
+    }
+
+    /// Block hash in the byte order used by the circuits.
+    pub fn hash(&self, height: u64) -> H256 {
+        H256(self.header(height).block_hash().to_byte_array())
+    }
+
+    pub fn threshold(&self, height: u64) -> U256 {
+        U256::from_little_endian(&self.header(height).target().to_le_bytes())
+    }
+
+    /// Returns the nBits a next block with timestamp `time` must have, applying the retarget
+    /// formula at the start of each period whose previous period is part of the chain.
+    pub fn next_bits(&self, time: u32) -> u32 {
+        let tip = self.header(self.tip_height());
+        let next_height = self.tip_height() + 1;
+        let is_period_start = |height: u64| {
+            self.retarget_interval
+                .map_or(false, |interval| height % interval == 0)
+        };
+
+        match self.retarget_interval {
+            Some(interval)
+                if next_height % interval == 0 && next_height >= self.start_height + interval =>
+            {
+                let period_start = self.header(next_height - interval);
+                let bits = if self.enforce_bip94 {
+                    period_start.bits
+                } else {
+                    tip.bits
+                };
+                target_to_bits(&retarget_threshold(
+                    bits_to_target(bits.to_consensus()),
+                    period_start.time,
+                    tip.time,
+                    interval as u32 * self.target_spacing,
+                    &self.pow_limit,
+                ))
+            }
+            _ if self.min_difficulty_blocks && !is_period_start(next_height) => {
+                let min_difficulty_bits = target_to_bits(&self.pow_limit);
+                if time > tip.time + 2 * self.target_spacing {
+                    return min_difficulty_bits;
+                }
+
+                // walk back to the last block that is not at the minimum difficulty
+                let mut height = self.tip_height();
+                while height > self.start_height
+                    && !is_period_start(height)
+                    && self.header(height).bits.to_consensus() == min_difficulty_bits
+                {
+                    height -= 1;
+                }
+                self.header(height).bits.to_consensus()
+            }
+            _ => tip.bits.to_consensus(),
+        }
+    }
+
+    /// Mines a header on top of the tip with the given timestamp and nBits.
+    pub fn mine(&mut self, time: u32, bits: u32) -> Header {
+        let height = self.start_height + self.headers.len() as u64;
+        let mut header = Header {
+            version: Version::from_consensus(SYNTHETIC_VERSION),
+            prev_blockhash: self
+                .headers
+                .last()
+                .map_or(BlockHash::all_zeros(), |tip| tip.block_hash()),
+            // the coinbase is the only transaction, so its txid is the merkle root
+            merkle_root: TxMerkleNode::from_byte_array(
+                synthetic_coinbase(height, time).txid().to_byte_array(),
+            ),
+            time,
+            bits: CompactTarget::from_consensus(bits),
+            nonce: 0,
+        };
+
+        let target = header.target();
+        while Target::from_le_bytes(header.block_hash().to_byte_array()) > target {
+            header.nonce = header
+                .nonce
+                .checked_add(1)
+                .expect("no nonce satisfies the target");
+        }
+
+        self.heights.insert(header.block_hash(), height);
+        self.headers.push(header);
+        header
+    }
+
+    /// Mines `count` headers spaced `spacing` seconds apart, retargeting as required.
+    pub fn extend(&mut self, count: usize, spacing: u32) -> &mut Self {
+        for _ in 0..count {
+            let time = self.header(self.tip_height()).time + spacing;
+            self.mine(time, self.next_bits(time));
+        }
+        self
+    }
+
+    /// Runs `f` with this chain serving the headers for hints on the current thread.
+    pub fn serve<R>(&self, f: impl FnOnce() -> R) -> R {
+        let chain = self.clone();
+        registry::with_header_source(move || Ok(Box::new(chain.clone())), f)
+    }
+}
+
+impl HeaderSource for SyntheticChain {
+    fn get_header_by_height(&mut self, height: u64) -> FetchResult<Header> {
+        if height < self.start_height || height > self.tip_height() {
+            return Err(FetchError::HeightOutOfRange(height));
+        }
+        Ok(self.header(height))
+    }
+
+    fn get_header_by_hash(&mut self, hash: &BlockHash) -> FetchResult<Header> {
+        let height = self.get_header_height(hash)?;
+        Ok(self.header(height))
+    }
+
+    fn get_header_height(&mut self, hash: &BlockHash) -> FetchResult<u64> {
+        self.heights
+            .get(hash)
+            .copied()
+            .ok_or(FetchError::UnknownHash(*hash))
+    }
+
+    fn get_chain_tip(&mut self) -> FetchResult<(u64, BlockHash)> {
+        let height = self.tip_height();
+        Ok((height, self.header(height).block_hash()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_synthetic_chain_retarget_clamps() {
+        let bits = 0x200fffff;
+        let target = bits_to_target(bits);
+
+        // blocks found every minute hit the 1/4 timespan clamp
+        let mut fast = SyntheticChain::new(0, 1231006505, bits);
+        fast.extend(2016, 60);
+        assert_eq!(
+            fast.header(2016).bits.to_consensus(),
+            target_to_bits(&(&target / 4u32))
+        );
+        assert_eq!(fast.header(2015).bits.to_consensus(), bits);
+
+        // blocks found every 100 minutes hit the 4x timespan clamp
+        let mut slow = SyntheticChain::new(0, 1231006505, bits);
+        slow.extend(2016, 6000);
+        assert_eq!(
+            slow.header(2016).bits.to_consensus(),
+            target_to_bits(&(&target * 4u32))
+        );
+
+        for height in 1..=slow.tip_height() {
+            let header = slow.header(height);
+            assert_eq!(header.prev_blockhash, slow.header(height - 1).block_hash());
+            assert!(header.validate_pow(header.target()).is_ok());
+        }
+        assert_eq!(
+            slow.get_header_height(&slow.header(2016).block_hash())
+                .unwrap(),
+            2016
+        );
+    }
+
+    #[test]
+    fn test_synthetic_chain_min_difficulty() {
+        let bits = 0x200fffff;
+
+        let mut chain = SyntheticChain::new(0, 1231006505, bits);
+        chain.min_difficulty_blocks = true;
+        chain.extend(2, 600).extend(2, 1201).extend(1, 1200);
+
+        assert_eq!(chain.header(2).bits.to_consensus(), bits);
+        assert_eq!(chain.header(3).bits.to_consensus(), REGTEST_BITS);
+        assert_eq!(chain.header(4).bits.to_consensus(), REGTEST_BITS);
+        // walks back past both minimum difficulty blocks
+        assert_eq!(chain.header(5).bits.to_consensus(), bits);
+    }
+
+    #[test]
+    fn test_synthetic_chain_blocks() {
+        let mut chain = SyntheticChain::new(100, 1231006505, REGTEST_BITS);
+        chain.extend(2, 600);
+
+        for height in 100..=102 {
+            let block = chain.block(height);
+            assert!(block.check_merkle_root());
+            assert_eq!(block.bip34_block_height().unwrap(), height);
+        }
+    }
+}
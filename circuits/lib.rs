@@ -1,7 +1,10 @@
 pub mod builder;
 pub mod consts;
 pub mod input;
+pub mod mmr;
+pub mod params;
 pub mod retarget;
+pub mod tx;
 pub mod utils;
 pub mod vars;
 pub mod verify;
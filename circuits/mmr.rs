@@ -0,0 +1,284 @@
+use std::marker::PhantomData;
+
+use plonky2x::backend::circuit::Circuit;
+use plonky2x::prelude::{
+    ArrayVariable, Bytes32Variable, CircuitBuilder, PlonkParameters, U32Variable, U64Variable,
+};
+
+use crate::builder::header::BitcoinHeaderVerify;
+use crate::builder::mmr::BitcoinMmr;
+use crate::consts::MMR_MAX_PEAKS;
+use crate::params::ChainParams;
+use crate::retarget::BitcoinVerifyWithRetargetCircuit;
+use crate::vars::*;
+use crate::verify::BitcoinVerifyCircuit;
+
+/// Reads the prior MMR size and peaks from the evm input.
+fn read_mmr<L: PlonkParameters<D>, const D: usize>(
+    builder: &mut CircuitBuilder<L, D>,
+) -> (U32Variable, MmrPeaksVariable) {
+    let size = builder.evm_read::<U32Variable>();
+    let peaks = (0..MMR_MAX_PEAKS)
+        .map(|_| builder.evm_read::<Bytes32Variable>())
+        .collect::<Vec<_>>();
+    (size, ArrayVariable::from(peaks))
+}
+
+/// Appends the validated header hashes to the MMR and writes the prior root, the new root
+/// and the hash of the last header to the evm output.
+fn write_mmr_update<L: PlonkParameters<D>, const D: usize, const UPDATE_HEADERS_COUNT: usize>(
+    builder: &mut CircuitBuilder<L, D>,
+    size: &U32Variable,
+    peaks: &MmrPeaksVariable,
+    header_hashes: &ArrayVariable<BlockHashVariable, UPDATE_HEADERS_COUNT>,
+) {
+    let prev_root = builder.mmr_root(size, peaks);
+    let (new_size, new_peaks) = builder.mmr_append(size, peaks, header_hashes);
+    let new_root = builder.mmr_root(&new_size, &new_peaks);
+
+    builder.evm_write(prev_root);
+    builder.evm_write(new_root);
+    builder.evm_write(header_hashes[UPDATE_HEADERS_COUNT - 1]);
+}
+
+/// `VerifyCircuit` that commits to the header hashes with an MMR root instead of
+/// writing each hash.
+#[derive(Debug, Clone)]
+pub struct VerifyMmrCircuit<const UPDATE_HEADERS_COUNT: usize> {}
+
+impl<const UPDATE_HEADERS_COUNT: usize> Circuit for VerifyMmrCircuit<UPDATE_HEADERS_COUNT> {
+    fn define<L: PlonkParameters<D>, const D: usize>(builder: &mut CircuitBuilder<L, D>) {
+        let prev_header_hash = builder.evm_read::<BlockHashVariable>();
+        let threshold = builder.evm_read::<ThresholdVariable>();
+        let reference_time = builder.evm_read::<U32Variable>();
+        let max_future_block_time = builder.evm_read::<U32Variable>();
+        let (mmr_size, mmr_peaks) = read_mmr(builder);
+
+        let (header_hashes, total_work, median_time_past) = builder.verify::<UPDATE_HEADERS_COUNT>(
+            prev_header_hash,
+            threshold,
+            reference_time,
+            max_future_block_time,
+        );
+
+        write_mmr_update(builder, &mmr_size, &mmr_peaks, &header_hashes);
+        builder.evm_write::<WorkVariable>(total_work);
+        builder.evm_write::<U32Variable>(median_time_past);
+    }
+
+    fn register_generators<L: PlonkParameters<D>, const D: usize>(
+        generator_registry: &mut plonky2x::prelude::HintRegistry<L, D>,
+    ) where
+        <<L as PlonkParameters<D>>::Config as plonky2::plonk::config::GenericConfig<D>>::Hasher:
+            plonky2::plonk::config::AlgebraicHasher<L::Field>,
+    {
+        generator_registry
+            .register_hint::<crate::verify::VerifyOffchainInputs<UPDATE_HEADERS_COUNT>>();
+    }
+}
+
+/// `VerifyWithRetargetCircuit` that commits to the header hashes with an MMR root instead
+/// of writing each hash.
+#[derive(Debug, Clone)]
+pub struct VerifyWithRetargetMmrCircuit<P: ChainParams, const UPDATE_HEADERS_COUNT: usize> {
+    _params: PhantomData<P>,
+}
+
+impl<P: ChainParams, const UPDATE_HEADERS_COUNT: usize> Circuit
+    for VerifyWithRetargetMmrCircuit<P, UPDATE_HEADERS_COUNT>
+{
+    fn define<L: PlonkParameters<D>, const D: usize>(builder: &mut CircuitBuilder<L, D>) {
+        let prev_block_number = builder.evm_read::<U64Variable>();
+        let prev_header_hash = builder.evm_read::<BlockHashVariable>();
+        let period_start_hash = builder.evm_read::<BlockHashVariable>();
+        let current_threshold = builder.evm_read::<ThresholdVariable>();
+        let reference_time = builder.evm_read::<U32Variable>();
+        let max_future_block_time = builder.evm_read::<U32Variable>();
+        let (mmr_size, mmr_peaks) = read_mmr(builder);
+
+        let (header_hashes, next_threshold, total_work, median_time_past) = builder
+            .verify_with_retargeting::<P, UPDATE_HEADERS_COUNT>(
+                prev_block_number,
+                prev_header_hash,
+                period_start_hash,
+                current_threshold,
+                reference_time,
+                max_future_block_time,
+            );
+
+        write_mmr_update(builder, &mmr_size, &mmr_peaks, &header_hashes);
+        builder.evm_write::<ThresholdVariable>(next_threshold);
+        builder.evm_write::<WorkVariable>(total_work);
+        builder.evm_write::<U32Variable>(median_time_past);
+    }
+
+    fn register_generators<L: PlonkParameters<D>, const D: usize>(
+        generator_registry: &mut plonky2x::prelude::HintRegistry<L, D>,
+    ) where
+        <<L as PlonkParameters<D>>::Config as plonky2::plonk::config::GenericConfig<D>>::Hasher:
+            plonky2::plonk::config::AlgebraicHasher<L::Field>,
+    {
+        generator_registry
+            .register_hint::<crate::retarget::VerifyOffchainInputs<P, UPDATE_HEADERS_COUNT>>();
+    }
+}
+
+/// Proves that a header is the leaf at a given position of an MMR, so that historical
+/// blocks can be checked against the root without storing every hash.
+#[derive(Debug, Clone)]
+pub struct MmrInclusionCircuit {}
+
+impl Circuit for MmrInclusionCircuit {
+    fn define<L: PlonkParameters<D>, const D: usize>(builder: &mut CircuitBuilder<L, D>) {
+        let (mmr_size, mmr_peaks) = read_mmr(builder);
+        let position = builder.evm_read::<U32Variable>();
+        let header_bytes = builder.evm_read::<HeaderBytesVariable>();
+        let proof = (0..MMR_MAX_PEAKS)
+            .map(|_| builder.evm_read::<Bytes32Variable>())
+            .collect::<Vec<_>>();
+
+        let header = builder.validate_header(&header_bytes);
+        builder.mmr_verify_inclusion(
+            &mmr_size,
+            &mmr_peaks,
+            &position,
+            &header.hash,
+            &ArrayVariable::from(proof),
+        );
+        let root = builder.mmr_root(&mmr_size, &mmr_peaks);
+
+        builder.evm_write(root);
+        builder.evm_write(position);
+        builder.evm_write(header.hash);
+        builder.evm_write(header.merkle_root);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use bitcoincore_rpc::bitcoin::consensus::serialize;
+    use bitcoincore_rpc::bitcoin::hashes::Hash;
+    use ethers::types::{H256, U256};
+    use plonky2x::prelude::{DefaultBuilder, GateRegistry, HintRegistry};
+
+    use super::*;
+    use crate::consts::MAX_FUTURE_BLOCK_TIME;
+    use crate::input::mmr::HeaderMmr;
+    use crate::input::synthetic::{SyntheticChain, REGTEST_BITS};
+
+    #[test]
+    fn test_verify_mmr_serialization() {
+        env::set_var("RUST_LOG", "debug");
+        env_logger::try_init().unwrap_or_default();
+
+        const UPDATE_HEADERS_COUNT: usize = 2;
+        let mut builder = DefaultBuilder::new();
+
+        log::debug!("Defining circuit");
+        VerifyMmrCircuit::<UPDATE_HEADERS_COUNT>::define(&mut builder);
+        let circuit = builder.build();
+        log::debug!("Done building circuit");
+
+        let mut hint_registry = HintRegistry::new();
+        let mut gate_registry = GateRegistry::new();
+        VerifyMmrCircuit::<UPDATE_HEADERS_COUNT>::register_generators(&mut hint_registry);
+        VerifyMmrCircuit::<UPDATE_HEADERS_COUNT>::register_gates(&mut gate_registry);
+
+        circuit.test_serializers(&gate_registry, &hint_registry);
+    }
+
+    #[test]
+    fn test_verify_mmr_synthetic_10() {
+        env::set_var("RUST_LOG", "debug");
+        env_logger::try_init().unwrap_or_default();
+
+        const UPDATE_HEADERS_COUNT: usize = 10;
+        let mut chain = SyntheticChain::new(0, 1231006505, REGTEST_BITS);
+        chain.extend(UPDATE_HEADERS_COUNT, 600);
+
+        // the MMR already commits to the anchor block
+        let mut mmr = HeaderMmr::default();
+        mmr.append(chain.hash(0));
+
+        let mut builder = DefaultBuilder::new();
+        VerifyMmrCircuit::<UPDATE_HEADERS_COUNT>::define(&mut builder);
+        let circuit = builder.build();
+
+        let mut input = circuit.input();
+        input.evm_write::<BlockHashVariable>(chain.hash(0));
+        input.evm_write::<ThresholdVariable>(chain.threshold(0));
+        input.evm_write::<U32Variable>(chain.header(chain.tip_height()).time);
+        input.evm_write::<U32Variable>(MAX_FUTURE_BLOCK_TIME);
+        input.evm_write::<U32Variable>(mmr.size);
+        for peak in mmr.peaks {
+            input.evm_write::<Bytes32Variable>(peak);
+        }
+
+        let (proof, mut output) = chain.serve(|| circuit.prove(&input));
+        circuit.verify(&proof, &input, &output);
+
+        let prev_root = mmr.root();
+        for height in 1..=UPDATE_HEADERS_COUNT as u64 {
+            mmr.append(chain.hash(height));
+        }
+
+        assert_eq!(output.evm_read::<Bytes32Variable>(), prev_root);
+        assert_eq!(output.evm_read::<Bytes32Variable>(), mmr.root());
+        assert_eq!(
+            output.evm_read::<BlockHashVariable>(),
+            chain.hash(UPDATE_HEADERS_COUNT as u64)
+        );
+        assert_eq!(
+            output.evm_read::<WorkVariable>(),
+            U256::from(2 * UPDATE_HEADERS_COUNT)
+        );
+        assert_eq!(output.evm_read::<U32Variable>(), chain.header(5).time);
+    }
+
+    #[test]
+    fn test_mmr_inclusion() {
+        env::set_var("RUST_LOG", "debug");
+        env_logger::try_init().unwrap_or_default();
+
+        let mut chain = SyntheticChain::new(0, 1231006505, REGTEST_BITS);
+        chain.extend(10, 600);
+        let leaves = (0..=10)
+            .map(|height| chain.hash(height))
+            .collect::<Vec<_>>();
+        let mmr = HeaderMmr::from_leaves(&leaves);
+
+        let mut builder = DefaultBuilder::new();
+        MmrInclusionCircuit::define(&mut builder);
+        let circuit = builder.build();
+
+        let position = 6;
+        let header = chain.header(position as u64);
+
+        let mut input = circuit.input();
+        input.evm_write::<U32Variable>(mmr.size);
+        for peak in mmr.peaks {
+            input.evm_write::<Bytes32Variable>(peak);
+        }
+        input.evm_write::<U32Variable>(position);
+        input.evm_write::<HeaderBytesVariable>(serialize(&header).try_into().unwrap());
+        for sibling in HeaderMmr::inclusion_proof(&leaves, position) {
+            input.evm_write::<Bytes32Variable>(sibling);
+        }
+
+        let (proof, mut output) = circuit.prove(&input);
+        circuit.verify(&proof, &input, &output);
+
+        assert_eq!(output.evm_read::<Bytes32Variable>(), mmr.root());
+        assert_eq!(output.evm_read::<U32Variable>(), position);
+        assert_eq!(
+            output.evm_read::<BlockHashVariable>(),
+            chain.hash(position as u64)
+        );
+        assert_eq!(
+            output.evm_read::<Bytes32Variable>(),
+            H256(header.merkle_root.to_byte_array())
+        );
+    }
+}
@@ -0,0 +1,270 @@
+use std::fmt::Debug;
+use std::str::FromStr;
+
+use ethers::types::H256;
+use num_bigint::BigUint;
+
+/// Heights from which the soft forks that raised the minimum block version are enforced.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VersionActivations {
+    /// BIP34, height in coinbase: version 2.
+    pub bip34_height: u64,
+    /// BIP66, strict DER signatures: version 3.
+    pub bip66_height: u64,
+    /// BIP65, OP_CHECKLOCKTIMEVERIFY: version 4.
+    pub bip65_height: u64,
+}
+
+pub const MAINNET_VERSION_ACTIVATIONS: VersionActivations = VersionActivations {
+    bip34_height: 227931,
+    bip66_height: 363725,
+    bip65_height: 388381,
+};
+
+pub const TESTNET3_VERSION_ACTIVATIONS: VersionActivations = VersionActivations {
+    bip34_height: 21111,
+    bip66_height: 330776,
+    bip65_height: 581885,
+};
+
+/// Testnet4, signet and regtest enforce all three soft forks from the first block.
+pub const BURIED_VERSION_ACTIVATIONS: VersionActivations = VersionActivations {
+    bip34_height: 1,
+    bip66_height: 1,
+    bip65_height: 1,
+};
+
+impl VersionActivations {
+    /// Activation heights with the minimum version they enforce, by increasing version.
+    pub fn min_versions(&self) -> [(u64, u32); 3] {
+        [
+            (self.bip34_height, 2),
+            (self.bip66_height, 3),
+            (self.bip65_height, 4),
+        ]
+    }
+
+    /// Returns the minimum version of a block at `height`, or `None` before BIP34.
+    pub fn min_version(&self, height: u64) -> Option<i32> {
+        self.min_versions()
+            .iter()
+            .filter(|(activation_height, _)| height >= *activation_height)
+            .map(|(_, version)| *version as i32)
+            .max()
+    }
+
+    /// Returns true if a block at `height` may have `version`. Like Bitcoin Core, versions
+    /// are compared as signed integers, so versions with the top bit set are too low.
+    pub fn is_valid_version(&self, height: u64, version: i32) -> bool {
+        self.min_version(height)
+            .map_or(true, |min_version| version >= min_version)
+    }
+}
+
+/// Consensus parameters of a Bitcoin network, shared by the circuits and the native code.
+pub trait ChainParams: Debug + Clone + Send + Sync + 'static {
+    /// Number of blocks between difficulty adjustments.
+    const DIFFICULTY_ADJUSTMENT_INTERVAL: u64;
+    /// Expected number of seconds between blocks.
+    const POW_TARGET_SPACING: u32;
+    /// Highest allowed target, as big-endian hex.
+    const POW_LIMIT: &'static str;
+    /// Keeps the target unchanged at every adjustment, like regtest's `fPowNoRetargeting`.
+    const POW_NO_RETARGETING: bool;
+    /// Allows a block to use the minimum difficulty if its timestamp is more than twice the
+    /// target spacing after its parent, like testnet's `fPowAllowMinDifficultyBlocks`.
+    const POW_ALLOW_MIN_DIFFICULTY_BLOCKS: bool;
+    /// Retargets from the target of the first block of the period instead of the last, and
+    /// rejects a first block more than 10 minutes earlier than its parent, as BIP94 does on
+    /// testnet4.
+    const ENFORCE_BIP94: bool;
+    /// Hash of the genesis block, as displayed by Bitcoin Core.
+    const GENESIS_HASH: &'static str;
+    const VERSION_ACTIVATIONS: VersionActivations;
+
+    fn pow_target_timespan() -> u32 {
+        Self::DIFFICULTY_ADJUSTMENT_INTERVAL as u32 * Self::POW_TARGET_SPACING
+    }
+
+    /// Panics if a target up to the pow limit could overflow 256 bits while retargeting,
+    /// which Bitcoin Core rules out with the same bound in its chainparams sanity check.
+    fn pow_limit() -> BigUint {
+        let pow_limit = BigUint::parse_bytes(Self::POW_LIMIT.as_bytes(), 16).unwrap();
+        let max_target = ((BigUint::from(1u32) << 256) - 1u32) / (4 * Self::pow_target_timespan());
+        assert!(
+            Self::POW_NO_RETARGETING || pow_limit < max_target,
+            "pow limit {} overflows retargeting",
+            Self::POW_LIMIT
+        );
+        pow_limit
+    }
+
+    /// Target of minimum difficulty blocks: the pow limit rounded down to nBits precision.
+    fn min_difficulty_target() -> BigUint {
+        let pow_limit = Self::pow_limit();
+        // the first byte of the three byte mantissa must not have the sign bit set
+        let bytes = pow_limit.to_bytes_be();
+        let mantissa_length = if bytes[0] & 0x80 != 0 { 2 } else { 3 };
+        let shift = 8 * bytes.len().saturating_sub(mantissa_length);
+        (pow_limit >> shift) << shift
+    }
+
+    /// Hash of the genesis block in the byte order used by the circuits.
+    fn genesis_hash() -> H256 {
+        let mut hash = H256::from_str(Self::GENESIS_HASH).unwrap();
+        hash.0.reverse();
+        hash
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Mainnet;
+
+impl ChainParams for Mainnet {
+    const DIFFICULTY_ADJUSTMENT_INTERVAL: u64 = 2016;
+    const POW_TARGET_SPACING: u32 = 600;
+    const POW_LIMIT: &'static str =
+        "00000000ffffffffffffffffffffffffffffffffffffffffffffffffffffffff";
+    const POW_NO_RETARGETING: bool = false;
+    const POW_ALLOW_MIN_DIFFICULTY_BLOCKS: bool = false;
+    const ENFORCE_BIP94: bool = false;
+    const GENESIS_HASH: &'static str =
+        "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f";
+    const VERSION_ACTIVATIONS: VersionActivations = MAINNET_VERSION_ACTIVATIONS;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Testnet3;
+
+impl ChainParams for Testnet3 {
+    const DIFFICULTY_ADJUSTMENT_INTERVAL: u64 = 2016;
+    const POW_TARGET_SPACING: u32 = 600;
+    const POW_LIMIT: &'static str =
+        "00000000ffffffffffffffffffffffffffffffffffffffffffffffffffffffff";
+    const POW_NO_RETARGETING: bool = false;
+    const POW_ALLOW_MIN_DIFFICULTY_BLOCKS: bool = true;
+    const ENFORCE_BIP94: bool = false;
+    const GENESIS_HASH: &'static str =
+        "000000000933ea01ad0ee984209779baaec3ced90fa3f408719526f8d77f4943";
+    const VERSION_ACTIVATIONS: VersionActivations = TESTNET3_VERSION_ACTIVATIONS;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Testnet4;
+
+impl ChainParams for Testnet4 {
+    const DIFFICULTY_ADJUSTMENT_INTERVAL: u64 = 2016;
+    const POW_TARGET_SPACING: u32 = 600;
+    const POW_LIMIT: &'static str =
+        "00000000ffffffffffffffffffffffffffffffffffffffffffffffffffffffff";
+    const POW_NO_RETARGETING: bool = false;
+    const POW_ALLOW_MIN_DIFFICULTY_BLOCKS: bool = true;
+    const ENFORCE_BIP94: bool = true;
+    const GENESIS_HASH: &'static str =
+        "00000000da84f2bafbbc53dee25a72ae507ff4914b867c565be350b0da8bf043";
+    const VERSION_ACTIVATIONS: VersionActivations = BURIED_VERSION_ACTIVATIONS;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Signet;
+
+impl ChainParams for Signet {
+    const DIFFICULTY_ADJUSTMENT_INTERVAL: u64 = 2016;
+    const POW_TARGET_SPACING: u32 = 600;
+    const POW_LIMIT: &'static str =
+        "00000377ae000000000000000000000000000000000000000000000000000000";
+    const POW_NO_RETARGETING: bool = false;
+    const POW_ALLOW_MIN_DIFFICULTY_BLOCKS: bool = false;
+    const ENFORCE_BIP94: bool = false;
+    const GENESIS_HASH: &'static str =
+        "00000008819873e925422c1ff0f99f7cc9bbb232af63a077a480a3633bee1ef6";
+    const VERSION_ACTIVATIONS: VersionActivations = BURIED_VERSION_ACTIVATIONS;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Regtest;
+
+impl ChainParams for Regtest {
+    const DIFFICULTY_ADJUSTMENT_INTERVAL: u64 = 2016;
+    const POW_TARGET_SPACING: u32 = 600;
+    const POW_LIMIT: &'static str =
+        "7fffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff";
+    const POW_NO_RETARGETING: bool = true;
+    const POW_ALLOW_MIN_DIFFICULTY_BLOCKS: bool = true;
+    const ENFORCE_BIP94: bool = false;
+    const GENESIS_HASH: &'static str =
+        "0f9188f13cb7b2c71f2a335e3a4fc328bf5beb436012afca590b1a11466e2206";
+    const VERSION_ACTIVATIONS: VersionActivations = BURIED_VERSION_ACTIVATIONS;
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoincore_rpc::bitcoin::blockdata::constants::genesis_block;
+    use bitcoincore_rpc::bitcoin::consensus::Params;
+    use bitcoincore_rpc::bitcoin::hashes::Hash;
+    use bitcoincore_rpc::bitcoin::Network;
+
+    use super::*;
+
+    #[test]
+    fn test_version_activations() {
+        let activations = MAINNET_VERSION_ACTIVATIONS;
+
+        assert_eq!(activations.min_version(227930), None);
+        assert_eq!(activations.min_version(227931), Some(2));
+        assert_eq!(activations.min_version(363725), Some(3));
+        assert_eq!(activations.min_version(388381), Some(4));
+
+        assert!(activations.is_valid_version(227930, 1));
+        assert!(!activations.is_valid_version(227931, 1));
+        assert!(activations.is_valid_version(840000, 0x20000000));
+        assert!(!activations.is_valid_version(840000, 0x80000000u32 as i32));
+        assert!(!BURIED_VERSION_ACTIVATIONS.is_valid_version(1, 3));
+    }
+
+    fn assert_params<P: ChainParams>(network: Network) {
+        let params = Params::new(network);
+        let genesis = genesis_block(network);
+        assert_eq!(P::genesis_hash().0, genesis.block_hash().to_byte_array());
+        assert!(BigUint::from_bytes_be(&genesis.header.target().to_be_bytes()) <= P::pow_limit());
+        assert_eq!(P::pow_target_timespan() as u64, params.pow_target_timespan);
+        assert_eq!(P::POW_NO_RETARGETING, params.no_pow_retargeting);
+        assert_eq!(
+            P::POW_ALLOW_MIN_DIFFICULTY_BLOCKS,
+            params.allow_min_difficulty_blocks
+        );
+        assert_eq!(
+            P::min_difficulty_target(),
+            BigUint::from_bytes_be(&params.pow_limit.to_be_bytes())
+        );
+    }
+
+    /// Regtest with retargeting, whose pow limit is too high for it.
+    #[derive(Debug, Clone)]
+    struct OverflowingRegtest;
+
+    impl ChainParams for OverflowingRegtest {
+        const DIFFICULTY_ADJUSTMENT_INTERVAL: u64 = Regtest::DIFFICULTY_ADJUSTMENT_INTERVAL;
+        const POW_TARGET_SPACING: u32 = Regtest::POW_TARGET_SPACING;
+        const POW_LIMIT: &'static str = Regtest::POW_LIMIT;
+        const POW_NO_RETARGETING: bool = false;
+        const POW_ALLOW_MIN_DIFFICULTY_BLOCKS: bool = Regtest::POW_ALLOW_MIN_DIFFICULTY_BLOCKS;
+        const ENFORCE_BIP94: bool = Regtest::ENFORCE_BIP94;
+        const GENESIS_HASH: &'static str = Regtest::GENESIS_HASH;
+        const VERSION_ACTIVATIONS: VersionActivations = Regtest::VERSION_ACTIVATIONS;
+    }
+
+    #[test]
+    #[should_panic(expected = "overflows retargeting")]
+    fn test_pow_limit_overflow() {
+        OverflowingRegtest::pow_limit();
+    }
+
+    #[test]
+    fn test_chain_params() {
+        assert_params::<Mainnet>(Network::Bitcoin);
+        assert_params::<Testnet3>(Network::Testnet);
+        assert_params::<Signet>(Network::Signet);
+        assert_params::<Regtest>(Network::Regtest);
+    }
+}
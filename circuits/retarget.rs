@@ -1,43 +1,69 @@
-use ethers::types::U256;
+use std::marker::PhantomData;
+
+use bitcoincore_rpc::bitcoin::hashes::Hash;
+use bitcoincore_rpc::bitcoin::BlockHash;
+use ethers::types::{H256, U256};
 use plonky2x::backend::circuit::Circuit;
 use plonky2x::frontend::hint::simple::hint::Hint;
 use plonky2x::frontend::vars::U256Variable;
 use plonky2x::prelude::{
-    ArrayVariable, CircuitBuilder, PlonkParameters, U64Variable, ValueStream, VariableStream,
+    ArrayVariable, Bytes32Variable, BytesVariable, CircuitBuilder, PlonkParameters, U32Variable,
+    U64Variable, ValueStream, VariableStream,
 };
 
 use serde::{Deserialize, Serialize};
 
+use crate::builder::header::BitcoinHeaderVerify;
 use crate::builder::multi::BitcoinMultiVerify;
+use crate::builder::tx::BitcoinTxVerify;
+use crate::consts::{COINBASE_MAX_BYTES, HEADER_BYTES_LENGTH, TX_MERKLE_MAX_DEPTH};
+use crate::input::error::FetchResult;
 use crate::input::InputDataFetcher;
+use crate::params::ChainParams;
 use crate::vars::*;
 
 pub trait BitcoinVerifyWithRetargetCircuit<L: PlonkParameters<D>, const D: usize> {
-    fn verify_with_retargeting<const UPDATE_HEADERS_COUNT: usize>(
+    fn verify_with_retargeting<P: ChainParams, const UPDATE_HEADERS_COUNT: usize>(
         &mut self,
         prev_block_number: U64Variable,
         prev_header_hash: BlockHashVariable,
         period_start_hash: BlockHashVariable,
         current_threshold: ThresholdVariable,
-    ) -> (ArrayVariable<BlockHashVariable, UPDATE_HEADERS_COUNT>, ThresholdVariable);
+        reference_time: U32Variable,
+        max_future_block_time: U32Variable,
+    ) -> (
+        ArrayVariable<BlockHashVariable, UPDATE_HEADERS_COUNT>,
+        ThresholdVariable,
+        WorkVariable,
+        U32Variable,
+    );
 }
 
 impl<L: PlonkParameters<D>, const D: usize> BitcoinVerifyWithRetargetCircuit<L, D>
     for CircuitBuilder<L, D>
 {
-    fn verify_with_retargeting<const UPDATE_HEADERS_COUNT: usize>(
+    fn verify_with_retargeting<P: ChainParams, const UPDATE_HEADERS_COUNT: usize>(
         &mut self,
         prev_block_number: U64Variable,
         prev_header_hash: BlockHashVariable,
         period_start_hash: BlockHashVariable,
         current_threshold: ThresholdVariable,
-    ) -> (ArrayVariable<BlockHashVariable, UPDATE_HEADERS_COUNT>, ThresholdVariable) {
+        reference_time: U32Variable,
+        max_future_block_time: U32Variable,
+    ) -> (
+        ArrayVariable<BlockHashVariable, UPDATE_HEADERS_COUNT>,
+        ThresholdVariable,
+        WorkVariable,
+        U32Variable,
+    ) {
         let mut input_stream = VariableStream::new();
         input_stream.write(&prev_block_number);
         input_stream.write(&prev_header_hash);
         let output_stream = self.hint(
             input_stream,
-            VerifyOffchainInputs::<UPDATE_HEADERS_COUNT> {},
+            VerifyOffchainInputs::<P, UPDATE_HEADERS_COUNT> {
+                _params: PhantomData,
+            },
         );
 
         let next_threshold = output_stream.read::<ThresholdVariable>(self);
@@ -45,8 +71,12 @@ impl<L: PlonkParameters<D>, const D: usize> BitcoinVerifyWithRetargetCircuit<L,
         let period_end_header_bytes = output_stream.read::<HeaderBytesVariable>(self);
         let update_headers_bytes =
             output_stream.read::<ArrayVariable<HeaderBytesVariable, UPDATE_HEADERS_COUNT>>(self);
+        let context_headers_bytes = output_stream.read::<TimestampContextVariable>(self);
+
+        // headers may be at most max_future_block_time ahead of the reference time
+        let max_timestamp = self.max_timestamp(&reference_time, &max_future_block_time);
 
-        self.validate_headers_with_retargeting(
+        self.validate_headers_with_retargeting::<P, UPDATE_HEADERS_COUNT>(
             &prev_block_number,
             &prev_header_hash,
             &period_start_hash,
@@ -54,40 +84,103 @@ impl<L: PlonkParameters<D>, const D: usize> BitcoinVerifyWithRetargetCircuit<L,
             &next_threshold,
             &period_start_header_bytes,
             &period_end_header_bytes,
+            &max_timestamp,
+            &context_headers_bytes,
             &update_headers_bytes,
         )
     }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct VerifyOffchainInputs<const UPDATE_HEADERS_COUNT: usize> {}
+#[serde(bound = "")]
+pub struct VerifyOffchainInputs<P: ChainParams, const UPDATE_HEADERS_COUNT: usize> {
+    _params: PhantomData<P>,
+}
 
-// #[async_trait]
-impl<const UPDATE_HEADERS_COUNT: usize, L: PlonkParameters<D>, const D: usize> Hint<L, D>
-    for VerifyOffchainInputs<UPDATE_HEADERS_COUNT>
+impl<P: ChainParams, const UPDATE_HEADERS_COUNT: usize>
+    VerifyOffchainInputs<P, UPDATE_HEADERS_COUNT>
 {
-    fn hint(&self, input_stream: &mut ValueStream<L, D>, output_stream: &mut ValueStream<L, D>) {
-        let prev_block_number = input_stream.read_value::<U64Variable>();
-        let prev_header_hash = input_stream.read_value::<BlockHashVariable>();
-
-        let mut input_fetcher = InputDataFetcher::default();
+    fn fetch_inputs(
+        &self,
+        prev_block_number: u64,
+        prev_header_hash: H256,
+    ) -> FetchResult<(
+        U256,
+        [u8; HEADER_BYTES_LENGTH],
+        [u8; HEADER_BYTES_LENGTH],
+        Vec<[u8; HEADER_BYTES_LENGTH]>,
+        Vec<[u8; HEADER_BYTES_LENGTH]>,
+    )> {
+        let mut input_fetcher = InputDataFetcher::from_registry()?;
+        let prev_hash = BlockHash::from_byte_array(prev_header_hash.0);
+
+        let interval = P::DIFFICULTY_ADJUSTMENT_INTERVAL;
+        let period_start_block_number = prev_block_number - prev_block_number % interval;
+        let period_end_block_number = period_start_block_number + interval - 1;
+
+        // headers up to the first block of the next period, following the branch of prev_hash
+        let next_period_start_index = (period_end_block_number - prev_block_number) as usize;
+        let descendants = input_fetcher.get_descendants(
+            &prev_hash,
+            UPDATE_HEADERS_COUNT.max(next_period_start_index + 1),
+        )?;
+
+        // the first block of a period is never a minimum difficulty block, so it witnesses the
+        // target that walking back past those blocks ends at
+        let period_start_header =
+            input_fetcher.get_ancestor(&prev_hash, prev_block_number, period_start_block_number)?;
+        let period_start_header_bytes = input_fetcher.to_bytes(&period_start_header)?;
+
+        let period_end_header = if next_period_start_index == 0 {
+            input_fetcher.get_ancestor(&prev_hash, prev_block_number, period_end_block_number)?
+        } else {
+            descendants[next_period_start_index - 1]
+        };
+        let period_end_header_bytes = input_fetcher.to_bytes(&period_end_header)?;
+
+        let next_period_start_header = descendants[next_period_start_index];
+        let next_threshold =
+            U256::from_little_endian(&next_period_start_header.target().to_le_bytes());
 
-        let period_start_block_number = prev_block_number - prev_block_number % 2016;
-        let period_end_block_number = period_start_block_number + 2015;
+        let update_headers_bytes = descendants[..UPDATE_HEADERS_COUNT]
+            .iter()
+            .map(|header| input_fetcher.to_bytes(header))
+            .collect::<FetchResult<Vec<_>>>()?;
 
-        let period_start_header = input_fetcher.get_header_by_height(period_start_block_number);
-        let period_start_header_bytes = input_fetcher.to_bytes(&period_start_header);
+        let context_headers_bytes = input_fetcher.get_timestamp_context_inputs(prev_header_hash)?;
 
-        let period_end_header = input_fetcher.get_header_by_height(period_end_block_number);
-        let period_end_header_bytes = input_fetcher.to_bytes(&period_end_header);
+        Ok((
+            next_threshold,
+            period_start_header_bytes,
+            period_end_header_bytes,
+            update_headers_bytes,
+            context_headers_bytes,
+        ))
+    }
+}
 
-        let next_period_start_header =
-            input_fetcher.get_header_by_height(period_end_block_number + 1);
-        let next_threshold =
-            U256::from_little_endian(&next_period_start_header.target().to_le_bytes());
+// #[async_trait]
+impl<P: ChainParams, const UPDATE_HEADERS_COUNT: usize, L: PlonkParameters<D>, const D: usize>
+    Hint<L, D> for VerifyOffchainInputs<P, UPDATE_HEADERS_COUNT>
+{
+    fn hint(&self, input_stream: &mut ValueStream<L, D>, output_stream: &mut ValueStream<L, D>) {
+        let prev_block_number = input_stream.read_value::<U64Variable>();
+        let prev_header_hash = input_stream.read_value::<BlockHashVariable>();
 
-        let update_headers_bytes = input_fetcher
-            .get_update_headers_inputs::<UPDATE_HEADERS_COUNT>(prev_header_hash);
+        let (
+            next_threshold,
+            period_start_header_bytes,
+            period_end_header_bytes,
+            update_headers_bytes,
+            context_headers_bytes,
+        ) = self
+            .fetch_inputs(prev_block_number, prev_header_hash)
+            .unwrap_or_else(|e| {
+                panic!(
+                    "failed to fetch retarget inputs for block {} ({:?}): {}",
+                    prev_block_number, prev_header_hash, e
+                )
+            });
 
         output_stream.write_value::<ThresholdVariable>(next_threshold);
         output_stream.write_value::<HeaderBytesVariable>(period_start_header_bytes);
@@ -95,33 +188,120 @@ impl<const UPDATE_HEADERS_COUNT: usize, L: PlonkParameters<D>, const D: usize> H
         output_stream.write_value::<ArrayVariable<HeaderBytesVariable, UPDATE_HEADERS_COUNT>>(
             update_headers_bytes,
         );
+        output_stream.write_value::<TimestampContextVariable>(context_headers_bytes);
     }
 }
 
 #[derive(Debug, Clone)]
-pub struct VerifyWithRetargetCircuit<const UPDATE_HEADERS_COUNT: usize> {}
+pub struct VerifyWithRetargetCircuit<P: ChainParams, const UPDATE_HEADERS_COUNT: usize> {
+    _params: PhantomData<P>,
+}
 
-impl<const UPDATE_HEADERS_COUNT: usize> Circuit
-    for VerifyWithRetargetCircuit<UPDATE_HEADERS_COUNT>
+impl<P: ChainParams, const UPDATE_HEADERS_COUNT: usize> Circuit
+    for VerifyWithRetargetCircuit<P, UPDATE_HEADERS_COUNT>
 {
     fn define<L: PlonkParameters<D>, const D: usize>(builder: &mut CircuitBuilder<L, D>) {
         let prev_block_number = builder.evm_read::<U64Variable>();
         let prev_header_hash = builder.evm_read::<BlockHashVariable>();
         let period_start_hash = builder.evm_read::<BlockHashVariable>();
         let current_threshold = builder.evm_read::<ThresholdVariable>();
+        let reference_time = builder.evm_read::<U32Variable>();
+        let max_future_block_time = builder.evm_read::<U32Variable>();
 
-        let (header_hashes, next_threshold) = builder
-            .verify_with_retargeting::<UPDATE_HEADERS_COUNT>(
+        let (header_hashes, next_threshold, total_work, median_time_past) = builder
+            .verify_with_retargeting::<P, UPDATE_HEADERS_COUNT>(
                 prev_block_number,
                 prev_header_hash,
                 period_start_hash,
                 current_threshold,
+                reference_time,
+                max_future_block_time,
             );
         
         header_hashes.as_vec().iter().for_each(|hash| {
             builder.evm_write(*hash);
         });
         builder.evm_write::<U256Variable>(next_threshold);
+        builder.evm_write::<WorkVariable>(total_work);
+        builder.evm_write::<U32Variable>(median_time_past);
+    }
+
+    fn register_generators<L: PlonkParameters<D>, const D: usize>(
+        generator_registry: &mut plonky2x::prelude::HintRegistry<L, D>,
+    ) where
+        <<L as PlonkParameters<D>>::Config as plonky2::plonk::config::GenericConfig<D>>::Hasher:
+            plonky2::plonk::config::AlgebraicHasher<L::Field>,
+    {
+        generator_registry.register_hint::<VerifyOffchainInputs<P, UPDATE_HEADERS_COUNT>>();
+    }
+}
+
+/// `VerifyWithRetargetCircuit` that derives `prev_block_number` from the BIP34 height in
+/// the coinbase of the previous block instead of taking it as an input.
+#[derive(Debug, Clone)]
+pub struct VerifyWithRetargetBip34Circuit<P: ChainParams, const UPDATE_HEADERS_COUNT: usize> {
+    _params: PhantomData<P>,
+}
+
+impl<P: ChainParams, const UPDATE_HEADERS_COUNT: usize> Circuit
+    for VerifyWithRetargetBip34Circuit<P, UPDATE_HEADERS_COUNT>
+{
+    fn define<L: PlonkParameters<D>, const D: usize>(builder: &mut CircuitBuilder<L, D>) {
+        let prev_header_hash = builder.evm_read::<BlockHashVariable>();
+        let period_start_hash = builder.evm_read::<BlockHashVariable>();
+        let current_threshold = builder.evm_read::<ThresholdVariable>();
+        let reference_time = builder.evm_read::<U32Variable>();
+        let max_future_block_time = builder.evm_read::<U32Variable>();
+
+        // previous header and its coinbase with the inclusion branch
+        let prev_header_bytes = builder.evm_read::<HeaderBytesVariable>();
+        let coinbase_length = builder.evm_read::<U32Variable>();
+        let coinbase_bytes = builder.evm_read::<BytesVariable<COINBASE_MAX_BYTES>>();
+        let coinbase_depth = builder.evm_read::<U32Variable>();
+        let coinbase_branch = (0..TX_MERKLE_MAX_DEPTH)
+            .map(|_| builder.evm_read::<Bytes32Variable>())
+            .collect::<Vec<_>>();
+
+        let prev_header = builder.validate_header(&prev_header_bytes);
+        builder.assert_is_equal(prev_header.hash, prev_header_hash);
+        let prev_block_number = builder.verify_coinbase_height(
+            &prev_header,
+            &coinbase_bytes,
+            &coinbase_length,
+            &coinbase_depth,
+            &ArrayVariable::from(coinbase_branch),
+        );
+
+        // the coinbase only commits to the height from BIP34 activation, which also
+        // requires a version of at least 2
+        let _true = builder._true();
+        let bip34_height = builder.constant::<U64Variable>(P::VERSION_ACTIVATIONS.bip34_height);
+        let is_bip34_active = builder.lte(bip34_height, prev_block_number);
+        builder.assert_is_equal(is_bip34_active, _true);
+        let is_valid_version = builder.is_valid_version(
+            &prev_header.version,
+            &prev_block_number,
+            &P::VERSION_ACTIVATIONS,
+        );
+        builder.assert_is_equal(is_valid_version, _true);
+
+        let (header_hashes, next_threshold, total_work, median_time_past) = builder
+            .verify_with_retargeting::<P, UPDATE_HEADERS_COUNT>(
+                prev_block_number,
+                prev_header_hash,
+                period_start_hash,
+                current_threshold,
+                reference_time,
+                max_future_block_time,
+            );
+
+        header_hashes.as_vec().iter().for_each(|hash| {
+            builder.evm_write(*hash);
+        });
+        builder.evm_write::<ThresholdVariable>(next_threshold);
+        builder.evm_write::<WorkVariable>(total_work);
+        builder.evm_write::<U32Variable>(median_time_past);
+        builder.evm_write::<U64Variable>(prev_block_number);
     }
 
     fn register_generators<L: PlonkParameters<D>, const D: usize>(
@@ -130,7 +310,7 @@ impl<const UPDATE_HEADERS_COUNT: usize> Circuit
         <<L as PlonkParameters<D>>::Config as plonky2::plonk::config::GenericConfig<D>>::Hasher:
             plonky2::plonk::config::AlgebraicHasher<L::Field>,
     {
-        generator_registry.register_hint::<VerifyOffchainInputs<UPDATE_HEADERS_COUNT>>();
+        generator_registry.register_hint::<VerifyOffchainInputs<P, UPDATE_HEADERS_COUNT>>();
     }
 }
 
@@ -138,10 +318,111 @@ impl<const UPDATE_HEADERS_COUNT: usize> Circuit
 mod tests {
     use std::env;
 
+    use bitcoincore_rpc::bitcoin::consensus::serialize;
     use ethers::types::H256;
+    use num_bigint::BigUint;
     use plonky2x::prelude::{bytes32, DefaultBuilder, GateRegistry, HintRegistry};
 
     use super::*;
+    use crate::consts::{MAX_FUTURE_BLOCK_TIME, MAX_TIMEWARP};
+    use crate::input::merkle::TxInclusionInputs;
+    use crate::input::synthetic::{bits_to_target, target_to_bits, SyntheticChain, REGTEST_BITS};
+    use crate::params::{Mainnet, Regtest, VersionActivations, BURIED_VERSION_ACTIVATIONS};
+    use crate::utils::compute_work;
+
+    /// Retargets every 20 blocks of 30 seconds so that synthetic chains cross a retarget
+    /// quickly, with a pow limit just within Bitcoin Core's overflow bound for that timespan.
+    #[derive(Debug, Clone)]
+    struct FastRetargeting;
+
+    impl ChainParams for FastRetargeting {
+        const DIFFICULTY_ADJUSTMENT_INTERVAL: u64 = 20;
+        const POW_TARGET_SPACING: u32 = 30;
+        const POW_LIMIT: &'static str =
+            "000fffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff";
+        const POW_NO_RETARGETING: bool = false;
+        const POW_ALLOW_MIN_DIFFICULTY_BLOCKS: bool = false;
+        const ENFORCE_BIP94: bool = false;
+        const GENESIS_HASH: &'static str = Regtest::GENESIS_HASH;
+        const VERSION_ACTIVATIONS: VersionActivations = BURIED_VERSION_ACTIVATIONS;
+    }
+
+    /// `FastRetargeting` with the minimum difficulty blocks of testnet3.
+    #[derive(Debug, Clone)]
+    struct FastMinDifficulty;
+
+    impl ChainParams for FastMinDifficulty {
+        const DIFFICULTY_ADJUSTMENT_INTERVAL: u64 = FastRetargeting::DIFFICULTY_ADJUSTMENT_INTERVAL;
+        const POW_TARGET_SPACING: u32 = FastRetargeting::POW_TARGET_SPACING;
+        const POW_LIMIT: &'static str = FastRetargeting::POW_LIMIT;
+        const POW_NO_RETARGETING: bool = false;
+        const POW_ALLOW_MIN_DIFFICULTY_BLOCKS: bool = true;
+        const ENFORCE_BIP94: bool = false;
+        const GENESIS_HASH: &'static str = Regtest::GENESIS_HASH;
+        const VERSION_ACTIVATIONS: VersionActivations = BURIED_VERSION_ACTIVATIONS;
+    }
+
+    /// `FastRetargeting` with the BIP94 rules of testnet4.
+    #[derive(Debug, Clone)]
+    struct FastBip94;
+
+    impl ChainParams for FastBip94 {
+        const DIFFICULTY_ADJUSTMENT_INTERVAL: u64 = FastRetargeting::DIFFICULTY_ADJUSTMENT_INTERVAL;
+        const POW_TARGET_SPACING: u32 = FastRetargeting::POW_TARGET_SPACING;
+        const POW_LIMIT: &'static str = FastRetargeting::POW_LIMIT;
+        const POW_NO_RETARGETING: bool = false;
+        const POW_ALLOW_MIN_DIFFICULTY_BLOCKS: bool = false;
+        const ENFORCE_BIP94: bool = true;
+        const GENESIS_HASH: &'static str = Regtest::GENESIS_HASH;
+        const VERSION_ACTIVATIONS: VersionActivations = BURIED_VERSION_ACTIVATIONS;
+    }
+
+    /// `FastRetargeting` with BIP34 activating at height 15.
+    #[derive(Debug, Clone)]
+    struct FastLateBip34;
+
+    impl ChainParams for FastLateBip34 {
+        const DIFFICULTY_ADJUSTMENT_INTERVAL: u64 = FastRetargeting::DIFFICULTY_ADJUSTMENT_INTERVAL;
+        const POW_TARGET_SPACING: u32 = FastRetargeting::POW_TARGET_SPACING;
+        const POW_LIMIT: &'static str = FastRetargeting::POW_LIMIT;
+        const POW_NO_RETARGETING: bool = false;
+        const POW_ALLOW_MIN_DIFFICULTY_BLOCKS: bool = false;
+        const ENFORCE_BIP94: bool = false;
+        const GENESIS_HASH: &'static str = Regtest::GENESIS_HASH;
+        const VERSION_ACTIVATIONS: VersionActivations = VersionActivations {
+            bip34_height: 15,
+            bip66_height: 15,
+            bip65_height: 15,
+        };
+    }
+
+    /// nBits of the pow limit of `FastRetargeting`.
+    const FAST_POW_LIMIT_BITS: u32 = 0x1f0fffff;
+
+    #[test]
+    fn test_verify_bip34_serialization() {
+        env::set_var("RUST_LOG", "debug");
+        env_logger::try_init().unwrap_or_default();
+
+        const UPDATE_HEADERS_COUNT: usize = 2;
+        let mut builder = DefaultBuilder::new();
+
+        log::debug!("Defining circuit");
+        VerifyWithRetargetBip34Circuit::<Mainnet, UPDATE_HEADERS_COUNT>::define(&mut builder);
+        let circuit = builder.build();
+        log::debug!("Done building circuit");
+
+        let mut hint_registry = HintRegistry::new();
+        let mut gate_registry = GateRegistry::new();
+        VerifyWithRetargetBip34Circuit::<Mainnet, UPDATE_HEADERS_COUNT>::register_generators(
+            &mut hint_registry,
+        );
+        VerifyWithRetargetBip34Circuit::<Mainnet, UPDATE_HEADERS_COUNT>::register_gates(
+            &mut gate_registry,
+        );
+
+        circuit.test_serializers(&gate_registry, &hint_registry);
+    }
 
     #[test]
     fn test_verify_serialization() {
@@ -152,31 +433,36 @@ mod tests {
         let mut builder = DefaultBuilder::new();
 
         log::debug!("Defining circuit");
-        VerifyWithRetargetCircuit::<UPDATE_HEADERS_COUNT>::define(&mut builder);
+        VerifyWithRetargetCircuit::<Mainnet, UPDATE_HEADERS_COUNT>::define(&mut builder);
         let circuit = builder.build();
         log::debug!("Done building circuit");
 
         let mut hint_registry = HintRegistry::new();
         let mut gate_registry = GateRegistry::new();
-        VerifyWithRetargetCircuit::<UPDATE_HEADERS_COUNT>::register_generators(&mut hint_registry);
-        VerifyWithRetargetCircuit::<UPDATE_HEADERS_COUNT>::register_gates(&mut gate_registry);
+        VerifyWithRetargetCircuit::<Mainnet, UPDATE_HEADERS_COUNT>::register_generators(
+            &mut hint_registry,
+        );
+        VerifyWithRetargetCircuit::<Mainnet, UPDATE_HEADERS_COUNT>::register_gates(
+            &mut gate_registry,
+        );
 
         circuit.test_serializers(&gate_registry, &hint_registry);
     }
 
-    fn test_verify_with_retargeting_template<const UPDATE_HEADERS_COUNT: usize>(
+    fn test_verify_with_retargeting_template<P: ChainParams, const UPDATE_HEADERS_COUNT: usize>(
         prev_block_number: u64,
         prev_header_hash: H256,
         period_start_hash: H256,
         current_threshold: U256,
-    ) -> (Vec<H256>, U256) {
+        reference_time: u32,
+    ) -> (Vec<H256>, U256, U256, u32) {
         env::set_var("RUST_LOG", "debug");
         env_logger::try_init().unwrap_or_default();
 
         let mut builder = DefaultBuilder::new();
 
         log::debug!("Defining circuit");
-        VerifyWithRetargetCircuit::<UPDATE_HEADERS_COUNT>::define(&mut builder);
+        VerifyWithRetargetCircuit::<P, UPDATE_HEADERS_COUNT>::define(&mut builder);
 
         log::debug!("Building circuit");
         let circuit = builder.build();
@@ -187,6 +473,8 @@ mod tests {
         input.evm_write::<BlockHashVariable>(prev_header_hash);
         input.evm_write::<BlockHashVariable>(period_start_hash);
         input.evm_write::<ThresholdVariable>(current_threshold);
+        input.evm_write::<U32Variable>(reference_time);
+        input.evm_write::<U32Variable>(MAX_FUTURE_BLOCK_TIME);
 
         log::debug!("Generating proof");
         let (proof, mut output) = circuit.prove(&input);
@@ -204,7 +492,13 @@ mod tests {
         let next_threshold = output.evm_read::<ThresholdVariable>();
         log::debug!("next_threshold {:?}", next_threshold);
 
-        return (hashes, next_threshold);
+        let total_work = output.evm_read::<WorkVariable>();
+        log::debug!("total_work {:?}", total_work);
+
+        let median_time_past = output.evm_read::<U32Variable>();
+        log::debug!("median_time_past {}", median_time_past);
+
+        return (hashes, next_threshold, total_work, median_time_past);
     }
 
     #[test]
@@ -218,11 +512,12 @@ mod tests {
         let current_threshold =
             U256::from_dec_str("8825801199382903987726989797449454220615414953524072026210304")
                 .unwrap();
-        let (mut hashes, next_threshold) = test_verify_with_retargeting_template::<UPDATE_HEADERS_COUNT>(
+        let (mut hashes, next_threshold, _, _) = test_verify_with_retargeting_template::<Mainnet, UPDATE_HEADERS_COUNT>(
             prev_block_number,
             prev_header_hash,
             period_start_hash,
             current_threshold,
+            1700000000,
         );
         assert_eq!(hashes.len(), UPDATE_HEADERS_COUNT);
         assert_eq!(hashes.pop().unwrap(), bytes32!("17420ce85462a303d8e0d77da1fc3513f6d97f3987533da16803000000000000"));
@@ -245,11 +540,12 @@ mod tests {
             "26959535291011309493156476344723991336010898738574164086137773096960",
         )
         .unwrap();
-        let (mut hashes, next_threshold) = test_verify_with_retargeting_template::<UPDATE_HEADERS_COUNT>(
+        let (mut hashes, next_threshold, total_work, _) = test_verify_with_retargeting_template::<Mainnet, UPDATE_HEADERS_COUNT>(
             prev_block_number,
             prev_header_hash,
             period_start_hash,
             current_threshold,
+            1700000000,
         );
         assert_eq!(hashes.len(), UPDATE_HEADERS_COUNT);
         assert_eq!(hashes.pop().unwrap(), bytes32!("d3d69a0d275b623bcca42606b012a37e506a54d9b7d9b0796c5e45cc00000000"));
@@ -258,6 +554,7 @@ mod tests {
             U256::from_dec_str("26959535291011309493156476344723991336010898738574164086137773096960")
                 .unwrap()
         );
+        assert_eq!(total_work, U256::from(0x100010001u64 * UPDATE_HEADERS_COUNT as u64));
     }
 
     #[test]
@@ -273,11 +570,240 @@ mod tests {
             "26959535291011309493156476344723991336010898738574164086137773096960",
         )
         .unwrap();
-        test_verify_with_retargeting_template::<UPDATE_HEADERS_COUNT>(
+        test_verify_with_retargeting_template::<Mainnet, UPDATE_HEADERS_COUNT>(
             prev_block_number,
             prev_header_hash,
             period_start_hash,
             current_threshold,
+            1700000000,
+        );
+    }
+
+    /// Verifies the last 10 headers of a synthetic chain and returns the next threshold and
+    /// the total work.
+    fn test_verify_with_retargeting_synthetic<P: ChainParams>(
+        chain: &SyntheticChain,
+    ) -> (U256, U256) {
+        const UPDATE_HEADERS_COUNT: usize = 10;
+        let prev_block_number = chain.tip_height() - UPDATE_HEADERS_COUNT as u64;
+
+        let (hashes, next_threshold, total_work, median_time_past) = chain.serve(|| {
+            test_verify_with_retargeting_template::<P, UPDATE_HEADERS_COUNT>(
+                prev_block_number,
+                chain.hash(prev_block_number),
+                chain.hash(0),
+                chain.threshold(0),
+                chain.header(chain.tip_height()).time,
+            )
+        });
+        assert_eq!(
+            hashes,
+            (prev_block_number + 1..=chain.tip_height())
+                .map(|height| chain.hash(height))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(median_time_past, chain.header(chain.tip_height() - 5).time);
+
+        (next_threshold, total_work)
+    }
+
+    #[test]
+    fn test_verify_with_retargeting_synthetic_14_10() {
+        // blocks found twice as fast about halve the target at height 20
+        let mut chain = SyntheticChain::new(0, 1231006505, FAST_POW_LIMIT_BITS)
+            .with_params::<FastRetargeting>();
+        chain.extend(24, 15);
+
+        let (next_threshold, _) = test_verify_with_retargeting_synthetic::<FastRetargeting>(&chain);
+        assert_eq!(next_threshold, chain.threshold(20));
+        assert!(next_threshold < chain.threshold(0));
+    }
+
+    #[test]
+    fn test_verify_with_retargeting_min_timespan_14_10() {
+        // blocks found six times as fast hit the 1/4 timespan clamp
+        let mut chain = SyntheticChain::new(0, 1231006505, FAST_POW_LIMIT_BITS)
+            .with_params::<FastRetargeting>();
+        chain.extend(24, 5);
+
+        let (next_threshold, _) = test_verify_with_retargeting_synthetic::<FastRetargeting>(&chain);
+        assert_eq!(next_threshold, chain.threshold(20));
+        assert_eq!(
+            chain.header(20).bits.to_consensus(),
+            target_to_bits(&(bits_to_target(FAST_POW_LIMIT_BITS) / 4u32))
         );
     }
+
+    #[test]
+    fn test_verify_with_retargeting_max_timespan_14_10() {
+        // blocks found ten times as slow hit the 4x timespan clamp
+        let bits = 0x1f03ffff;
+        let mut chain = SyntheticChain::new(0, 1231006505, bits).with_params::<FastRetargeting>();
+        chain.extend(24, 300);
+
+        let (next_threshold, _) = test_verify_with_retargeting_synthetic::<FastRetargeting>(&chain);
+        assert_eq!(next_threshold, chain.threshold(20));
+        assert_eq!(
+            chain.header(20).bits.to_consensus(),
+            target_to_bits(&(bits_to_target(bits) * 4u32))
+        );
+    }
+
+    #[test]
+    fn test_verify_with_retargeting_regtest_2010_10() {
+        // regtest keeps the target regardless of the block times
+        let mut chain = SyntheticChain::new(0, 1231006505, REGTEST_BITS).with_params::<Regtest>();
+        chain.extend(2020, 300);
+
+        let (next_threshold, _) = test_verify_with_retargeting_synthetic::<Regtest>(&chain);
+        assert_eq!(next_threshold, chain.threshold(0));
+    }
+
+    #[test]
+    fn test_verify_with_retargeting_min_difficulty_14_10() {
+        // late blocks at heights 15 and 19 take the minimum difficulty, the blocks after 15
+        // walk back to the target of the period
+        let mut chain =
+            SyntheticChain::new(0, 1231006505, 0x1f03ffff).with_params::<FastMinDifficulty>();
+        chain
+            .extend(14, 30)
+            .extend(1, 90)
+            .extend(3, 30)
+            .extend(1, 90)
+            .extend(5, 30);
+        assert_eq!(chain.header(15).bits.to_consensus(), FAST_POW_LIMIT_BITS);
+        assert_eq!(chain.threshold(16), chain.threshold(0));
+        assert_eq!(chain.header(19).bits.to_consensus(), FAST_POW_LIMIT_BITS);
+
+        // like testnet3, the retarget starts from the minimum difficulty of the last block
+        let (next_threshold, total_work) =
+            test_verify_with_retargeting_synthetic::<FastMinDifficulty>(&chain);
+        assert_eq!(next_threshold, chain.threshold(20));
+        assert!(next_threshold > chain.threshold(0));
+
+        // minimum difficulty blocks add the work of their own target
+        let expected_work = (15..=24)
+            .map(|height| {
+                let target = BigUint::from_bytes_le(&chain.header(height).target().to_le_bytes());
+                U256::from_little_endian(&compute_work(target).to_bytes_le())
+            })
+            .fold(U256::zero(), |acc, work| acc + work);
+        assert_eq!(total_work, expected_work);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_verify_with_retargeting_early_min_difficulty_14_10() {
+        // a block twice the target spacing after its parent may not take the minimum
+        // difficulty
+        let mut chain =
+            SyntheticChain::new(0, 1231006505, 0x1f03ffff).with_params::<FastMinDifficulty>();
+        chain.extend(14, 30);
+        let time = chain.header(14).time + 60;
+        chain.mine(time, FAST_POW_LIMIT_BITS);
+        chain.extend(9, 30);
+
+        test_verify_with_retargeting_synthetic::<FastMinDifficulty>(&chain);
+    }
+
+    /// Mines a chain whose period starts at height 20 `timewarp` seconds before its parent,
+    /// which is 2000 seconds late.
+    fn timewarp_chain(timewarp: u32) -> SyntheticChain {
+        let mut chain = SyntheticChain::new(0, 1231006505, 0x1f03ffff).with_params::<FastBip94>();
+        chain.extend(18, 30).extend(1, 2000);
+        let time = chain.header(19).time - timewarp;
+        chain.mine(time, chain.next_bits(time));
+        chain
+    }
+
+    #[test]
+    fn test_verify_with_retargeting_bip94_timewarp_10_10() {
+        // a period may start up to MAX_TIMEWARP seconds before its parent
+        let chain = timewarp_chain(MAX_TIMEWARP);
+
+        let (next_threshold, _) = test_verify_with_retargeting_synthetic::<FastBip94>(&chain);
+        assert_eq!(next_threshold, chain.threshold(20));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_verify_with_retargeting_bip94_early_period_start_10_10() {
+        let chain = timewarp_chain(MAX_TIMEWARP + 1);
+
+        test_verify_with_retargeting_synthetic::<FastBip94>(&chain);
+    }
+
+    /// Verifies the last 10 headers of a synthetic chain with the BIP34 variant and returns
+    /// the height it derives from the coinbase of the previous block.
+    fn test_verify_with_retargeting_bip34_synthetic<P: ChainParams>(chain: &SyntheticChain) -> u64 {
+        env::set_var("RUST_LOG", "debug");
+        env_logger::try_init().unwrap_or_default();
+
+        const UPDATE_HEADERS_COUNT: usize = 10;
+        let prev_block_number = chain.tip_height() - UPDATE_HEADERS_COUNT as u64;
+        let prev_block = chain.block(prev_block_number);
+        let coinbase =
+            TxInclusionInputs::from_block(&prev_block, &prev_block.txdata[0].txid()).unwrap();
+
+        let mut builder = DefaultBuilder::new();
+
+        log::debug!("Defining circuit");
+        VerifyWithRetargetBip34Circuit::<P, UPDATE_HEADERS_COUNT>::define(&mut builder);
+
+        log::debug!("Building circuit");
+        let circuit = builder.build();
+        log::debug!("Done building circuit");
+
+        let mut input = circuit.input();
+        input.evm_write::<BlockHashVariable>(chain.hash(prev_block_number));
+        input.evm_write::<BlockHashVariable>(chain.hash(0));
+        input.evm_write::<ThresholdVariable>(chain.threshold(0));
+        input.evm_write::<U32Variable>(chain.header(chain.tip_height()).time);
+        input.evm_write::<U32Variable>(MAX_FUTURE_BLOCK_TIME);
+        input.evm_write::<HeaderBytesVariable>(serialize(&coinbase.header).try_into().unwrap());
+        input.evm_write::<U32Variable>(coinbase.tx_bytes.len() as u32);
+        input.evm_write::<BytesVariable<COINBASE_MAX_BYTES>>(
+            coinbase.padded_tx_bytes::<COINBASE_MAX_BYTES>(),
+        );
+        input.evm_write::<U32Variable>(coinbase.branch.depth());
+        for sibling in coinbase.branch.padded_siblings() {
+            input.evm_write::<Bytes32Variable>(sibling);
+        }
+
+        log::debug!("Generating proof");
+        let (proof, mut output) = chain.serve(|| circuit.prove(&input));
+        log::debug!("Done generating proof");
+
+        circuit.verify(&proof, &input, &output);
+
+        for height in prev_block_number + 1..=chain.tip_height() {
+            assert_eq!(output.evm_read::<BlockHashVariable>(), chain.hash(height));
+        }
+        output.evm_read::<ThresholdVariable>();
+        output.evm_read::<WorkVariable>();
+        output.evm_read::<U32Variable>();
+        output.evm_read::<U64Variable>()
+    }
+
+    #[test]
+    fn test_verify_with_retargeting_bip34_synthetic_14_10() {
+        let mut chain = SyntheticChain::new(0, 1231006505, FAST_POW_LIMIT_BITS)
+            .with_params::<FastRetargeting>();
+        chain.extend(24, 30);
+
+        let prev_block_number =
+            test_verify_with_retargeting_bip34_synthetic::<FastRetargeting>(&chain);
+        assert_eq!(prev_block_number, 14);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_verify_with_retargeting_bip34_before_activation_14_10() {
+        // the coinbase of block 14 pushes its height, but BIP34 only activates at 15
+        let mut chain =
+            SyntheticChain::new(0, 1231006505, FAST_POW_LIMIT_BITS).with_params::<FastLateBip34>();
+        chain.extend(24, 30);
+
+        test_verify_with_retargeting_bip34_synthetic::<FastLateBip34>(&chain);
+    }
 }
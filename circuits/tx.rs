@@ -0,0 +1,327 @@
+use plonky2x::backend::circuit::Circuit;
+use plonky2x::prelude::{
+    ArrayVariable, Bytes32Variable, BytesVariable, CircuitBuilder, PlonkParameters, U32Variable,
+    U64Variable,
+};
+
+use crate::builder::header::BitcoinHeaderVerify;
+use crate::builder::tx::BitcoinTxVerify;
+use crate::consts::{COINBASE_MAX_BYTES, TX_MERKLE_MAX_DEPTH};
+use crate::vars::*;
+
+/// Reads a transaction inclusion proof and returns the header, the transaction bytes
+/// and the txid.
+fn read_tx_inclusion<L: PlonkParameters<D>, const D: usize, const MAX_TX_BYTES: usize>(
+    builder: &mut CircuitBuilder<L, D>,
+) -> (
+    BitcoinHeaderVariable,
+    BytesVariable<MAX_TX_BYTES>,
+    TxidVariable,
+) {
+    let header_bytes = builder.evm_read::<HeaderBytesVariable>();
+    let tx_length = builder.evm_read::<U32Variable>();
+    let tx_bytes = builder.evm_read::<BytesVariable<MAX_TX_BYTES>>();
+    let index = builder.evm_read::<U32Variable>();
+    let depth = builder.evm_read::<U32Variable>();
+    let branch = (0..TX_MERKLE_MAX_DEPTH)
+        .map(|_| builder.evm_read::<Bytes32Variable>())
+        .collect::<Vec<_>>();
+
+    let header = builder.validate_header(&header_bytes);
+    let txid = builder.verify_tx_inclusion(
+        &header,
+        &tx_bytes,
+        &tx_length,
+        &index,
+        &depth,
+        &ArrayVariable::from(branch),
+    );
+
+    (header, tx_bytes, txid)
+}
+
+/// Proves that a raw transaction of at most `MAX_TX_BYTES` bytes is committed to by the
+/// `merkle_root` of a header, i.e. a simplified payment verification (SPV) proof.
+#[derive(Debug, Clone)]
+pub struct TxInclusionCircuit<const MAX_TX_BYTES: usize> {}
+
+impl<const MAX_TX_BYTES: usize> Circuit for TxInclusionCircuit<MAX_TX_BYTES> {
+    fn define<L: PlonkParameters<D>, const D: usize>(builder: &mut CircuitBuilder<L, D>) {
+        let (header, _, txid) = read_tx_inclusion::<L, D, MAX_TX_BYTES>(builder);
+
+        builder.evm_write::<TxidVariable>(txid);
+        builder.evm_write::<BlockHashVariable>(header.hash);
+    }
+}
+
+/// Proves the value and the sha256 of the scriptPubKey of output `vout` of a transaction
+/// included in a block, e.g. a deposit to a known address.
+#[derive(Debug, Clone)]
+pub struct TxOutputCircuit<const MAX_TX_BYTES: usize> {}
+
+impl<const MAX_TX_BYTES: usize> Circuit for TxOutputCircuit<MAX_TX_BYTES> {
+    fn define<L: PlonkParameters<D>, const D: usize>(builder: &mut CircuitBuilder<L, D>) {
+        let (header, tx_bytes, txid) = read_tx_inclusion::<L, D, MAX_TX_BYTES>(builder);
+        let vout = builder.evm_read::<U32Variable>();
+
+        // the txid commits to the serialization without witness data
+        let _false = builder._false();
+        let has_witness = builder.has_witness_marker(&tx_bytes);
+        builder.assert_is_equal(has_witness, _false);
+
+        let output = builder.get_tx_output(&tx_bytes, &vout);
+        let script_hash = builder.calculate_script_hash(&output);
+
+        builder.evm_write::<TxidVariable>(txid);
+        builder.evm_write::<BlockHashVariable>(header.hash);
+        builder.evm_write::<U32Variable>(vout);
+        builder.evm_write::<U64Variable>(output.value);
+        builder.evm_write::<Bytes32Variable>(script_hash);
+    }
+}
+
+/// Proves that output `vout` of a transaction included in a block is an `OP_RETURN` and
+/// outputs its payload, e.g. the EVM recipient of a deposit.
+#[derive(Debug, Clone)]
+pub struct OpReturnCircuit<const MAX_TX_BYTES: usize> {}
+
+impl<const MAX_TX_BYTES: usize> Circuit for OpReturnCircuit<MAX_TX_BYTES> {
+    fn define<L: PlonkParameters<D>, const D: usize>(builder: &mut CircuitBuilder<L, D>) {
+        let (header, tx_bytes, txid) = read_tx_inclusion::<L, D, MAX_TX_BYTES>(builder);
+        let vout = builder.evm_read::<U32Variable>();
+
+        // the txid commits to the serialization without witness data
+        let _false = builder._false();
+        let has_witness = builder.has_witness_marker(&tx_bytes);
+        builder.assert_is_equal(has_witness, _false);
+
+        let output = builder.get_tx_output(&tx_bytes, &vout);
+        let (payload_length, payload) = builder.get_op_return_payload(&output);
+
+        builder.evm_write::<TxidVariable>(txid);
+        builder.evm_write::<BlockHashVariable>(header.hash);
+        builder.evm_write::<U32Variable>(vout);
+        builder.evm_write::<U32Variable>(payload_length);
+        builder.evm_write::<OpReturnPayloadVariable>(payload);
+    }
+}
+
+/// Proves that a transaction serialized with its witness is committed to by the witness
+/// commitment of the coinbase of a block, so that its witness data can be trusted. The
+/// coinbase must fit in `COINBASE_MAX_BYTES` with its SHA256 padding and have at most
+/// `TX_MAX_OUTPUTS` outputs, otherwise the block cannot be proven.
+#[derive(Debug, Clone)]
+pub struct WitnessInclusionCircuit<const MAX_TX_BYTES: usize> {}
+
+impl<const MAX_TX_BYTES: usize> Circuit for WitnessInclusionCircuit<MAX_TX_BYTES> {
+    fn define<L: PlonkParameters<D>, const D: usize>(builder: &mut CircuitBuilder<L, D>) {
+        let header_bytes = builder.evm_read::<HeaderBytesVariable>();
+        let coinbase_length = builder.evm_read::<U32Variable>();
+        let coinbase_bytes = builder.evm_read::<BytesVariable<COINBASE_MAX_BYTES>>();
+        let coinbase_depth = builder.evm_read::<U32Variable>();
+        let coinbase_branch = (0..TX_MERKLE_MAX_DEPTH)
+            .map(|_| builder.evm_read::<Bytes32Variable>())
+            .collect::<Vec<_>>();
+        let witness_reserved_value = builder.evm_read::<Bytes32Variable>();
+
+        let tx_length = builder.evm_read::<U32Variable>();
+        let tx_bytes = builder.evm_read::<BytesVariable<MAX_TX_BYTES>>();
+        let index = builder.evm_read::<U32Variable>();
+        let depth = builder.evm_read::<U32Variable>();
+        let branch = (0..TX_MERKLE_MAX_DEPTH)
+            .map(|_| builder.evm_read::<Bytes32Variable>())
+            .collect::<Vec<_>>();
+
+        // the witness commitment is in the coinbase, the first transaction of the block
+        let header = builder.validate_header(&header_bytes);
+        let coinbase_index = builder.zero::<U32Variable>();
+        builder.verify_tx_inclusion(
+            &header,
+            &coinbase_bytes,
+            &coinbase_length,
+            &coinbase_index,
+            &coinbase_depth,
+            &ArrayVariable::from(coinbase_branch),
+        );
+        let commitment = builder.get_witness_commitment(&coinbase_bytes);
+
+        // wtxids are computed like txids, over the serialization with witness data
+        let wtxid = builder.calculate_txid(&tx_bytes, &tx_length);
+        let witness_root =
+            builder.calculate_merkle_root(&wtxid, &index, &depth, &ArrayVariable::from(branch));
+        builder.verify_witness_commitment(&commitment, &witness_root, &witness_reserved_value);
+
+        builder.evm_write::<Bytes32Variable>(wtxid);
+        builder.evm_write::<BlockHashVariable>(header.hash);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use bitcoincore_rpc::bitcoin::absolute::LockTime;
+    use bitcoincore_rpc::bitcoin::blockdata::constants::genesis_block;
+    use bitcoincore_rpc::bitcoin::consensus::serialize;
+    use bitcoincore_rpc::bitcoin::hashes::{sha256, Hash};
+    use bitcoincore_rpc::bitcoin::{Amount, Block, Network, ScriptBuf, TxOut};
+    use ethers::types::H256;
+    use plonky2x::backend::circuit::{DefaultParameters, PublicInput};
+    use plonky2x::prelude::DefaultBuilder;
+
+    use super::*;
+    use crate::consts::WITNESS_COMMITMENT_HEADER;
+    use crate::input::merkle::WitnessInclusionInputs;
+
+    type L = DefaultParameters;
+    const D: usize = 2;
+
+    fn write_genesis_tx_inclusion<const MAX_TX_BYTES: usize>(input: &mut PublicInput<L, D>) {
+        // the coinbase is the only transaction of the genesis block, so the branch is empty
+        let block = genesis_block(Network::Bitcoin);
+        let tx = serialize(&block.txdata[0]);
+        let mut tx_bytes = [0u8; MAX_TX_BYTES];
+        tx_bytes[..tx.len()].copy_from_slice(&tx);
+
+        input.evm_write::<HeaderBytesVariable>(serialize(&block.header).try_into().unwrap());
+        input.evm_write::<U32Variable>(tx.len() as u32);
+        input.evm_write::<BytesVariable<MAX_TX_BYTES>>(tx_bytes);
+        input.evm_write::<U32Variable>(0);
+        input.evm_write::<U32Variable>(0);
+        for _ in 0..TX_MERKLE_MAX_DEPTH {
+            input.evm_write::<Bytes32Variable>(H256::zero());
+        }
+    }
+
+    #[test]
+    fn test_tx_inclusion_genesis() {
+        env::set_var("RUST_LOG", "debug");
+        env_logger::try_init().unwrap_or_default();
+
+        const MAX_TX_BYTES: usize = 256;
+
+        let mut builder = DefaultBuilder::new();
+        TxInclusionCircuit::<MAX_TX_BYTES>::define(&mut builder);
+        let circuit = builder.build();
+
+        let mut input = circuit.input();
+        write_genesis_tx_inclusion::<MAX_TX_BYTES>(&mut input);
+
+        let (proof, mut output) = circuit.prove(&input);
+        circuit.verify(&proof, &input, &output);
+
+        let block = genesis_block(Network::Bitcoin);
+        assert_eq!(
+            output.evm_read::<TxidVariable>(),
+            H256(block.txdata[0].txid().to_byte_array())
+        );
+        assert_eq!(
+            output.evm_read::<BlockHashVariable>(),
+            H256(block.block_hash().to_byte_array())
+        );
+    }
+
+    #[test]
+    fn test_tx_output_genesis() {
+        env::set_var("RUST_LOG", "debug");
+        env_logger::try_init().unwrap_or_default();
+
+        const MAX_TX_BYTES: usize = 256;
+
+        let mut builder = DefaultBuilder::new();
+        TxOutputCircuit::<MAX_TX_BYTES>::define(&mut builder);
+        let circuit = builder.build();
+
+        let mut input = circuit.input();
+        write_genesis_tx_inclusion::<MAX_TX_BYTES>(&mut input);
+        input.evm_write::<U32Variable>(0);
+
+        let (proof, mut output) = circuit.prove(&input);
+        circuit.verify(&proof, &input, &output);
+
+        let tx = &genesis_block(Network::Bitcoin).txdata[0];
+        assert_eq!(
+            output.evm_read::<TxidVariable>(),
+            H256(tx.txid().to_byte_array())
+        );
+        output.evm_read::<BlockHashVariable>();
+        assert_eq!(output.evm_read::<U32Variable>(), 0);
+        assert_eq!(output.evm_read::<U64Variable>(), 50 * 100_000_000);
+        assert_eq!(
+            output.evm_read::<Bytes32Variable>(),
+            H256(sha256::Hash::hash(tx.output[0].script_pubkey.as_bytes()).to_byte_array())
+        );
+    }
+
+    #[test]
+    fn test_witness_inclusion() {
+        env::set_var("RUST_LOG", "debug");
+        env_logger::try_init().unwrap_or_default();
+
+        const MAX_TX_BYTES: usize = 512;
+
+        let mut builder = DefaultBuilder::new();
+        WitnessInclusionCircuit::<MAX_TX_BYTES>::define(&mut builder);
+        let circuit = builder.build();
+
+        // a regtest block of three transactions with witnesses, committed to by the coinbase
+        let mut block = genesis_block(Network::Regtest);
+        block.txdata[0].input[0].witness.push([0u8; 32]);
+        for lock_time in 1..3 {
+            let mut tx = block.txdata[0].clone();
+            tx.lock_time = LockTime::from_consensus(lock_time);
+            tx.input[0].witness.push([lock_time as u8; 72]);
+            block.txdata.push(tx);
+        }
+
+        let witness_root = block.witness_root().unwrap();
+        let commitment = Block::compute_witness_commitment(&witness_root, &[0u8; 32]);
+        let script = [WITNESS_COMMITMENT_HEADER.as_slice(), commitment.as_ref()].concat();
+        block.txdata[0].output.push(TxOut {
+            value: Amount::ZERO,
+            script_pubkey: ScriptBuf::from_bytes(script),
+        });
+        block.header.merkle_root = block.compute_merkle_root().unwrap();
+        while block.header.validate_pow(block.header.target()).is_err() {
+            block.header.nonce += 1;
+        }
+
+        let tx = &block.txdata[2];
+        let inputs = WitnessInclusionInputs::from_block(&block, &tx.txid()).unwrap();
+        let mut tx_bytes = [0u8; MAX_TX_BYTES];
+        tx_bytes[..inputs.tx_bytes.len()].copy_from_slice(&inputs.tx_bytes);
+
+        let mut input = circuit.input();
+        input.evm_write::<HeaderBytesVariable>(
+            serialize(&inputs.coinbase.header).try_into().unwrap(),
+        );
+        input.evm_write::<U32Variable>(inputs.coinbase.tx_bytes.len() as u32);
+        input.evm_write::<BytesVariable<COINBASE_MAX_BYTES>>(
+            inputs.coinbase.padded_tx_bytes::<COINBASE_MAX_BYTES>(),
+        );
+        input.evm_write::<U32Variable>(inputs.coinbase.branch.depth());
+        for sibling in inputs.coinbase.branch.padded_siblings() {
+            input.evm_write::<Bytes32Variable>(sibling);
+        }
+        input.evm_write::<Bytes32Variable>(inputs.witness_reserved_value);
+        input.evm_write::<U32Variable>(inputs.tx_bytes.len() as u32);
+        input.evm_write::<BytesVariable<MAX_TX_BYTES>>(tx_bytes);
+        input.evm_write::<U32Variable>(inputs.branch.index);
+        input.evm_write::<U32Variable>(inputs.branch.depth());
+        for sibling in inputs.branch.padded_siblings() {
+            input.evm_write::<Bytes32Variable>(sibling);
+        }
+
+        let (proof, mut output) = circuit.prove(&input);
+        circuit.verify(&proof, &input, &output);
+
+        assert_eq!(
+            output.evm_read::<Bytes32Variable>(),
+            H256(tx.wtxid().to_byte_array())
+        );
+        assert_eq!(
+            output.evm_read::<BlockHashVariable>(),
+            H256(block.block_hash().to_byte_array())
+        );
+    }
+}
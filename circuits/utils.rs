@@ -4,9 +4,11 @@ use std::ops::AddAssign;
 use std::ops::MulAssign;
 
 use crate::consts::*;
+use crate::params::ChainParams;
 
+/// Splits the nBits of a header into the size byte and the 24-bit mantissa, sign bit included.
 pub fn compute_exp_and_mantissa(header_bytes: [u8; HEADER_BYTES_LENGTH]) -> (u32, u64) {
-    let exp = (header_bytes[HEADER_EXP_BYTE_INDEX] as u32) - 3;
+    let exp = header_bytes[HEADER_EXP_BYTE_INDEX] as u32;
 
     let mut mantissa = 0;
     mantissa += header_bytes[HEADER_MANTISSA_THIRD_BYTE_INDEX] as u64;
@@ -16,18 +18,30 @@ pub fn compute_exp_and_mantissa(header_bytes: [u8; HEADER_BYTES_LENGTH]) -> (u32
     (exp, mantissa)
 }
 
-pub fn compute_threshold(exp: u32, mantissa: u64) -> BigUint {
-    let mut threshold_bits = [false; 256];
+/// Decodes a compact target like Bitcoin Core's `arith_uint256::SetCompact`, returning the
+/// 256-bit threshold with the negative, overflow and canonical flags. An encoding is
+/// canonical if `GetCompact` returns it for a nonzero target.
+pub fn compute_threshold(exp: u32, mantissa: u64) -> (BigUint, bool, bool, bool) {
+    let mut word = mantissa & 0x007fffff;
+
+    // the sign bit is clear, the first mantissa byte is only zero if the next one would set
+    // the sign bit, and no nonzero byte is shifted out below the size
+    let is_leading_byte_set = word >> 16 != 0 || word & 0x8000 != 0;
+    let is_truncated = exp < 3 && word & ((1 << (8 * (3 - exp))) - 1) != 0;
+    let is_canonical = mantissa & 0x00800000 == 0 && is_leading_byte_set && !is_truncated;
+
+    let threshold = if exp <= 3 {
+        word >>= 8 * (3 - exp);
+        BigUint::from(word)
+    } else {
+        (BigUint::from(word) << (8 * (exp - 3))) % (BigUint::from(1u32) << 256)
+    };
 
-    for i in 0..256 {
-        if (i as u32) < 256 - (exp * 8)
-            && mantissa as u128 & (1u128 << (255u128 - (exp * 8) as u128 - i as u128)) != 0
-        {
-            threshold_bits[i] = true;
-        }
-    }
+    let is_negative = word != 0 && mantissa & 0x00800000 != 0;
+    let is_overflow =
+        word != 0 && (exp > 34 || (word > 0xff && exp > 33) || (word > 0xffff && exp > 32));
 
-    BigUint::from_bytes_be(&bits_to_bytes32(threshold_bits))
+    (threshold, is_negative, is_overflow, is_canonical)
 }
 
 pub fn compute_work(threshold: BigUint) -> BigUint {
@@ -43,14 +57,31 @@ pub fn compute_work(threshold: BigUint) -> BigUint {
     numerator / denominator
 }
 
-pub fn adjust_threshold(
+pub fn adjust_threshold<P: ChainParams>(
     threshold: BigUint,
     period_start_time: u32,
     period_end_time: u32,
 ) -> BigUint {
-    let pow_target_timespan = 14 * 24 * 60 * 60;
-    let pow_limit = BigUint::from_bytes_be(&[0xff; 28]);
+    if P::POW_NO_RETARGETING {
+        return threshold;
+    }
+
+    retarget_threshold(
+        threshold,
+        period_start_time,
+        period_end_time,
+        P::pow_target_timespan(),
+        &P::pow_limit(),
+    )
+}
 
+pub fn retarget_threshold(
+    threshold: BigUint,
+    period_start_time: u32,
+    period_end_time: u32,
+    pow_target_timespan: u32,
+    pow_limit: &BigUint,
+) -> BigUint {
     let timespan = period_end_time - period_start_time;
     let timespan = if timespan < pow_target_timespan / 4 {
         pow_target_timespan / 4
@@ -60,12 +91,12 @@ pub fn adjust_threshold(
         timespan
     };
 
-    let mut new_threshold = threshold * timespan / pow_target_timespan;
-    if new_threshold > pow_limit {
-        new_threshold = pow_limit;
+    let new_threshold = threshold * timespan / pow_target_timespan;
+    if new_threshold > *pow_limit {
+        pow_limit.clone()
+    } else {
+        new_threshold
     }
-
-    new_threshold
 }
 
 pub fn bits_to_bytes32(bits: [bool; 256]) -> [u8; 32] {
@@ -84,3 +115,45 @@ where
 
     U256Variable::from_variables_unsafe(&limbs.as_slice())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compute_threshold() {
+        let big = |value: u32, shift: u32| BigUint::from(value) << shift;
+
+        // vectors from Bitcoin Core's arith_uint256 tests
+        let cases = [
+            (0x00123456, big(0, 0), false, false, false),
+            (0x01003456, big(0, 0), false, false, false),
+            (0x02000056, big(0, 0), false, false, false),
+            (0x01123456, big(0x12, 0), false, false, false),
+            (0x01fedcba, big(0x7e, 0), true, false, false),
+            (0x02123456, big(0x1234, 0), false, false, false),
+            (0x04923456, big(0x123456, 8), true, false, false),
+            (0x05009234, big(0x9234, 16), false, false, true),
+            (0x20123456, big(0x123456, 232), false, false, true),
+            (0x21010000, big(0, 0), false, true, true),
+            (0x22000001, big(1, 248), false, false, false),
+            (0xff123456, big(0, 0), false, true, true),
+            // a zero target, a zero first byte, and the canonical forms of small targets
+            (0x00000000, big(0, 0), false, false, false),
+            (0x04001234, big(0x1234, 8), false, false, false),
+            (0x03123400, big(0x123400, 0), false, false, true),
+            (0x01120000, big(0x12, 0), false, false, true),
+            (0x02008000, big(0x80, 0), false, false, true),
+            (0x1d00ffff, big(0xffff, 208), false, false, true),
+        ];
+
+        for (bits, threshold, is_negative, is_overflow, is_canonical) in cases {
+            assert_eq!(
+                compute_threshold(bits >> 24, (bits & 0x00ffffff) as u64),
+                (threshold, is_negative, is_overflow, is_canonical),
+                "compact target {:#010x}",
+                bits
+            );
+        }
+    }
+}
@@ -1,22 +1,42 @@
-use crate::consts::HEADER_BYTES_LENGTH;
+use crate::consts::{
+    HEADER_BYTES_LENGTH, MEDIAN_TIME_SPAN, MMR_MAX_PEAKS, OP_RETURN_MAX_PAYLOAD_BYTES,
+    SCRIPT_PUBKEY_MAX_BYTES, TX_MERKLE_MAX_DEPTH,
+};
 
 use plonky2x::prelude::{
-    Bytes32Variable, BytesVariable, CircuitBuilder, CircuitVariable, PlonkParameters, RichField,
-    U256Variable, U32Variable, Variable,
+    ArrayVariable, Bytes32Variable, BytesVariable, CircuitBuilder, CircuitVariable,
+    PlonkParameters, RichField, U256Variable, U32Variable, U64Variable, Variable,
 };
 
 pub type HeaderBytesVariable = BytesVariable<HEADER_BYTES_LENGTH>;
 
 pub type BlockHashVariable = Bytes32Variable;
+pub type TimestampContextVariable = ArrayVariable<HeaderBytesVariable, MEDIAN_TIME_SPAN>;
 pub type ThresholdVariable = U256Variable;
 pub type WorkVariable = U256Variable;
+pub type MmrPeaksVariable = ArrayVariable<Bytes32Variable, MMR_MAX_PEAKS>;
+pub type MmrProofVariable = ArrayVariable<Bytes32Variable, MMR_MAX_PEAKS>;
+pub type TxidVariable = Bytes32Variable;
+pub type MerkleBranchVariable = ArrayVariable<Bytes32Variable, TX_MERKLE_MAX_DEPTH>;
+pub type ScriptBytesVariable = BytesVariable<SCRIPT_PUBKEY_MAX_BYTES>;
+pub type OpReturnPayloadVariable = BytesVariable<OP_RETURN_MAX_PAYLOAD_BYTES>;
 
 #[derive(Debug, Clone, CircuitVariable)]
 #[value_name(BitcoinHeaderType)]
 pub struct BitcoinHeaderVariable {
+    pub version: U32Variable,
     pub hash: BlockHashVariable,
     pub parent_hash: BlockHashVariable,
     pub merkle_root: Bytes32Variable,
     pub timestamp: U32Variable,
     pub threshold: ThresholdVariable,
 }
+
+#[derive(Debug, Clone, CircuitVariable)]
+#[value_name(TxOutputType)]
+pub struct TxOutputVariable {
+    pub value: U64Variable,
+    pub script_length: U32Variable,
+    /// scriptPubKey zero-padded to `SCRIPT_PUBKEY_MAX_BYTES`.
+    pub script: ScriptBytesVariable,
+}
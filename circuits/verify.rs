@@ -1,7 +1,7 @@
 use plonky2x::backend::circuit::Circuit;
 use plonky2x::frontend::hint::simple::hint::Hint;
 use plonky2x::prelude::{
-    ArrayVariable, CircuitBuilder, PlonkParameters, ValueStream, VariableStream,
+    ArrayVariable, CircuitBuilder, PlonkParameters, U32Variable, ValueStream, VariableStream,
 };
 
 use serde::{Deserialize, Serialize};
@@ -15,7 +15,13 @@ pub trait BitcoinVerifyCircuit<L: PlonkParameters<D>, const D: usize> {
         &mut self,
         prev_header_hash: BlockHashVariable,
         threshold: ThresholdVariable,
-    ) -> ArrayVariable<BlockHashVariable, UPDATE_HEADERS_COUNT>;
+        reference_time: U32Variable,
+        max_future_block_time: U32Variable,
+    ) -> (
+        ArrayVariable<BlockHashVariable, UPDATE_HEADERS_COUNT>,
+        WorkVariable,
+        U32Variable,
+    );
 }
 
 impl<L: PlonkParameters<D>, const D: usize> BitcoinVerifyCircuit<L, D> for CircuitBuilder<L, D> {
@@ -23,7 +29,13 @@ impl<L: PlonkParameters<D>, const D: usize> BitcoinVerifyCircuit<L, D> for Circu
         &mut self,
         prev_header_hash: BlockHashVariable,
         threshold: ThresholdVariable,
-    ) -> ArrayVariable<BlockHashVariable, UPDATE_HEADERS_COUNT> {
+        reference_time: U32Variable,
+        max_future_block_time: U32Variable,
+    ) -> (
+        ArrayVariable<BlockHashVariable, UPDATE_HEADERS_COUNT>,
+        WorkVariable,
+        U32Variable,
+    ) {
         let mut input_stream = VariableStream::new();
         input_stream.write(&prev_header_hash);
         let output_stream = self.hint(
@@ -32,8 +44,18 @@ impl<L: PlonkParameters<D>, const D: usize> BitcoinVerifyCircuit<L, D> for Circu
         );
         let update_headers_bytes =
             output_stream.read::<ArrayVariable<HeaderBytesVariable, UPDATE_HEADERS_COUNT>>(self);
+        let context_headers_bytes = output_stream.read::<TimestampContextVariable>(self);
 
-        self.validate_headers(&prev_header_hash, &threshold, &update_headers_bytes)
+        // headers may be at most max_future_block_time ahead of the reference time
+        let max_timestamp = self.max_timestamp(&reference_time, &max_future_block_time);
+
+        self.validate_headers(
+            &prev_header_hash,
+            &threshold,
+            &max_timestamp,
+            &context_headers_bytes,
+            &update_headers_bytes,
+        )
     }
 }
 
@@ -47,13 +69,25 @@ impl<const UPDATE_HEADERS_COUNT: usize, L: PlonkParameters<D>, const D: usize> H
     fn hint(&self, input_stream: &mut ValueStream<L, D>, output_stream: &mut ValueStream<L, D>) {
         let prev_header_hash = input_stream.read_value::<BlockHashVariable>();
 
-        let mut input_fetcher = InputDataFetcher::default();
-        let update_headers_bytes = input_fetcher
-            .get_update_headers_inputs::<UPDATE_HEADERS_COUNT>(prev_header_hash);
+        let (update_headers_bytes, context_headers_bytes) = InputDataFetcher::from_registry()
+            .and_then(|mut input_fetcher| {
+                Ok((
+                    input_fetcher
+                        .get_update_headers_inputs::<UPDATE_HEADERS_COUNT>(prev_header_hash)?,
+                    input_fetcher.get_timestamp_context_inputs(prev_header_hash)?,
+                ))
+            })
+            .unwrap_or_else(|e| {
+                panic!(
+                    "failed to fetch headers after {:?}: {}",
+                    prev_header_hash, e
+                )
+            });
 
         output_stream.write_value::<ArrayVariable<HeaderBytesVariable, UPDATE_HEADERS_COUNT>>(
             update_headers_bytes
         );
+        output_stream.write_value::<TimestampContextVariable>(context_headers_bytes);
     }
 }
 
@@ -64,13 +98,21 @@ impl<const UPDATE_HEADERS_COUNT: usize> Circuit for VerifyCircuit<UPDATE_HEADERS
     fn define<L: PlonkParameters<D>, const D: usize>(builder: &mut CircuitBuilder<L, D>) {
         let prev_header_hash = builder.evm_read::<BlockHashVariable>();
         let threshold = builder.evm_read::<ThresholdVariable>();
-
-        let header_hashes =
-            builder.verify::<UPDATE_HEADERS_COUNT>(prev_header_hash, threshold);
+        let reference_time = builder.evm_read::<U32Variable>();
+        let max_future_block_time = builder.evm_read::<U32Variable>();
+
+        let (header_hashes, total_work, median_time_past) = builder.verify::<UPDATE_HEADERS_COUNT>(
+            prev_header_hash,
+            threshold,
+            reference_time,
+            max_future_block_time,
+        );
 
         header_hashes.as_vec().iter().for_each(|hash| {
             builder.evm_write(*hash);
         });
+        builder.evm_write::<WorkVariable>(total_work);
+        builder.evm_write::<U32Variable>(median_time_past);
     }
 
     fn register_generators<L: PlonkParameters<D>, const D: usize>(
@@ -91,6 +133,8 @@ mod tests {
     use plonky2x::prelude::{bytes32, DefaultBuilder, GateRegistry, HintRegistry};
 
     use super::*;
+    use crate::consts::MAX_FUTURE_BLOCK_TIME;
+    use crate::input::synthetic::{SyntheticChain, REGTEST_BITS};
 
     #[test]
     fn test_verify_serialization() {
@@ -116,7 +160,8 @@ mod tests {
     fn test_verify_template<const UPDATE_HEADERS_COUNT: usize>(
         prev_header_hash: H256,
         threshold: U256,
-    ) -> Vec<H256> {
+        reference_time: u32,
+    ) -> (Vec<H256>, U256, u32) {
         env::set_var("RUST_LOG", "debug");
         env_logger::try_init().unwrap_or_default();
 
@@ -132,6 +177,8 @@ mod tests {
         let mut input = circuit.input();
         input.evm_write::<BlockHashVariable>(prev_header_hash);
         input.evm_write::<ThresholdVariable>(threshold);
+        input.evm_write::<U32Variable>(reference_time);
+        input.evm_write::<U32Variable>(MAX_FUTURE_BLOCK_TIME);
 
         log::debug!("Generating proof");
         let (proof, mut output) = circuit.prove(&input);
@@ -146,7 +193,13 @@ mod tests {
             hashes.push(hash);
         }
 
-        return hashes;
+        let total_work = output.evm_read::<WorkVariable>();
+        log::debug!("total_work {:?}", total_work);
+
+        let median_time_past = output.evm_read::<U32Variable>();
+        log::debug!("median_time_past {}", median_time_past);
+
+        return (hashes, total_work, median_time_past);
     }
 
     #[test]
@@ -157,9 +210,11 @@ mod tests {
             "26959535291011309493156476344723991336010898738574164086137773096960",
         )
         .unwrap();
-        let mut hashes = test_verify_template::<UPDATE_HEADERS_COUNT>(header, threshold);
+        let (mut hashes, total_work, _) =
+            test_verify_template::<UPDATE_HEADERS_COUNT>(header, threshold, 1700000000);
         assert_eq!(hashes.len(), UPDATE_HEADERS_COUNT);
         assert_eq!(hashes.pop().unwrap(), bytes32!("e915d9a478e3adf3186c07c61a22228b10fd87df343c92782ecc052c00000000"));
+        assert_eq!(total_work, U256::from(0x100010001u64 * UPDATE_HEADERS_COUNT as u64));
     }
 
     #[test]
@@ -169,8 +224,114 @@ mod tests {
         let threshold =
             U256::from_dec_str("9412783771427520201810837309176674245361798887059324066070528")
                 .unwrap();
-        let mut hashes = test_verify_template::<UPDATE_HEADERS_COUNT>(header, threshold);
+        let (mut hashes, _, _) =
+            test_verify_template::<UPDATE_HEADERS_COUNT>(header, threshold, 1700000000);
         assert_eq!(hashes.len(), UPDATE_HEADERS_COUNT);
         assert_eq!(hashes.pop().unwrap(), bytes32!("2a051182bc468e29d8fc925550ebac17ccec5bca3eaa107f5d04000000000000"));
     }
+
+    #[test]
+    fn test_verify_synthetic_10() {
+        const UPDATE_HEADERS_COUNT: usize = 10;
+        let mut chain = SyntheticChain::new(0, 1231006505, REGTEST_BITS);
+        chain.extend(UPDATE_HEADERS_COUNT, 600);
+
+        let (hashes, total_work, median_time_past) = chain.serve(|| {
+            test_verify_template::<UPDATE_HEADERS_COUNT>(
+                chain.hash(0),
+                chain.threshold(0),
+                chain.header(chain.tip_height()).time,
+            )
+        });
+        assert_eq!(
+            hashes,
+            (1..=UPDATE_HEADERS_COUNT as u64)
+                .map(|height| chain.hash(height))
+                .collect::<Vec<_>>()
+        );
+
+        // 2^256 / (0x7fffff << 232 + 1) rounds down to 2
+        assert_eq!(total_work, U256::from(2 * UPDATE_HEADERS_COUNT));
+
+        // the last 11 headers are heights 0 to 10, so the median is the header at height 5
+        assert_eq!(median_time_past, chain.header(5).time);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_verify_synthetic_median_time_past() {
+        const UPDATE_HEADERS_COUNT: usize = 11;
+        let mut chain = SyntheticChain::new(0, 1231006505, REGTEST_BITS);
+        chain.extend(UPDATE_HEADERS_COUNT - 1, 600);
+
+        // a timestamp equal to the median of the previous 11 headers is rejected
+        let median_time_past = chain.header(5).time;
+        chain.mine(median_time_past, REGTEST_BITS);
+
+        chain.serve(|| {
+            test_verify_template::<UPDATE_HEADERS_COUNT>(
+                chain.hash(0),
+                chain.threshold(0),
+                chain.header(chain.tip_height()).time,
+            )
+        });
+    }
+
+    #[test]
+    fn test_verify_synthetic_3() {
+        const UPDATE_HEADERS_COUNT: usize = 3;
+        let mut chain = SyntheticChain::new(0, 1231006505, REGTEST_BITS);
+        chain.extend(UPDATE_HEADERS_COUNT, 600);
+
+        let (_, _, median_time_past) = chain.serve(|| {
+            test_verify_template::<UPDATE_HEADERS_COUNT>(
+                chain.hash(0),
+                chain.threshold(0),
+                chain.header(chain.tip_height()).time,
+            )
+        });
+
+        // only heights 0 to 3 exist, so the median is the header at height 2
+        assert_eq!(median_time_past, chain.header(2).time);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_verify_synthetic_early_median_time_past() {
+        const UPDATE_HEADERS_COUNT: usize = 2;
+        let mut chain = SyntheticChain::new(0, 1231006505, REGTEST_BITS);
+        chain.extend(UPDATE_HEADERS_COUNT - 1, 600);
+
+        // the median of heights 0 and 1 is the header at height 1, so an earlier timestamp is
+        // rejected even though it is after the padding before genesis
+        let time = chain.header(1).time - 1;
+        chain.mine(time, REGTEST_BITS);
+
+        chain.serve(|| {
+            test_verify_template::<UPDATE_HEADERS_COUNT>(
+                chain.hash(0),
+                chain.threshold(0),
+                chain.header(chain.tip_height()).time,
+            )
+        });
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_verify_synthetic_future_time() {
+        const UPDATE_HEADERS_COUNT: usize = 10;
+        let mut chain = SyntheticChain::new(0, 1231006505, REGTEST_BITS);
+        chain.extend(UPDATE_HEADERS_COUNT, 600);
+
+        // the tip is one second beyond the allowed bound
+        let reference_time = chain.header(chain.tip_height()).time - MAX_FUTURE_BLOCK_TIME - 1;
+
+        chain.serve(|| {
+            test_verify_template::<UPDATE_HEADERS_COUNT>(
+                chain.hash(0),
+                chain.threshold(0),
+                reference_time,
+            )
+        });
+    }
 }